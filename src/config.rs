@@ -0,0 +1,412 @@
+use serde::{Deserialize, Serialize};
+use std::{
+  error::Error,
+  path::{Path, PathBuf},
+  time::Duration,
+};
+
+use crate::utils::{
+  history::Retention,
+  keystore,
+  ws::{self, WsListener},
+};
+
+/// Prefix of the environment variables overriding the configuration, as in
+/// `DESNET_NETWORK_PORT=8080` for `port` in the `[network]` section.
+pub const ENV_PREFIX: &str = "DESNET_";
+
+/// `~/.desnet/config.toml`, or `config.toml` in the working directory without a home.
+pub fn default_path() -> PathBuf {
+  match std::env::var_os("HOME") {
+    Some(home) => Path::new(&home).join(".desnet").join("config.toml"),
+    None => PathBuf::from("config.toml"),
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+  pub network: NetworkConfig,
+  pub gossipsub: GossipsubConfig,
+  pub kademlia: KademliaConfig,
+  pub identity: IdentityConfig,
+  pub ui: UiConfig,
+  pub server: ServerConfig,
+  pub history: HistoryConfig,
+  pub api: ApiConfig,
+  pub peers: PeersConfig,
+  /// `DESNET_*` variables ignored while loading, as unknown settings or invalid values.
+  #[serde(skip)]
+  pub ignored_vars: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+  /// Port to listen on. `0` lets the OS assign one.
+  pub port: u16,
+  /// Listen on QUIC, over UDP on the same port, as well as TCP.
+  pub quic: bool,
+  /// Port of the WebSocket listener for browsers, disabled when unset.
+  pub ws_port: Option<u16>,
+  /// PEM certificate chain and private key, to listen on secure WebSocket (`/wss`) instead.
+  pub ws_cert: Option<PathBuf>,
+  pub ws_key: Option<PathBuf>,
+  /// Discover peers on the local network with mDNS.
+  pub mdns: bool,
+  /// Public addresses, announced when they cannot be discovered, as behind the fly.io proxy.
+  pub external_addrs: Vec<String>,
+  /// Bootstrap node addresses, ending with `/p2p/<peer id>`.
+  #[serde(deserialize_with = "one_or_many")]
+  pub bootstrap: Vec<String>,
+  /// Seconds before an idle connection is closed.
+  pub idle_timeout_secs: u64,
+  /// Protocol version announced by identify.
+  pub identify_protocol: String,
+}
+
+impl Default for NetworkConfig {
+  fn default() -> Self {
+    NetworkConfig {
+      port: 0,
+      quic: true,
+      ws_port: None,
+      ws_cert: None,
+      ws_key: None,
+      mdns: false,
+      external_addrs: Vec::new(),
+      bootstrap: Vec::new(),
+      idle_timeout_secs: 3600,
+      identify_protocol: "/ipfs/id/1.0.0".to_string(),
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GossipsubConfig {
+  /// Room joined on start.
+  pub room: String,
+  pub heartbeat_interval_secs: u64,
+  /// Peers scoring below are not gossiped with.
+  pub gossip_threshold: f64,
+  /// Peers scoring below are not sent our messages.
+  pub publish_threshold: f64,
+  /// Peers scoring below are ignored altogether.
+  pub graylist_threshold: f64,
+  /// Room messages accepted from a peer in each window, 0 for no limit.
+  pub rate_limit_messages: u32,
+  pub rate_limit_window_secs: u64,
+}
+
+impl Default for GossipsubConfig {
+  fn default() -> Self {
+    GossipsubConfig {
+      room: "desnet-the-room".to_string(),
+      heartbeat_interval_secs: 10,
+      gossip_threshold: -10.0,
+      publish_threshold: -50.0,
+      graylist_threshold: -80.0,
+      rate_limit_messages: 30,
+      rate_limit_window_secs: 10,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct KademliaConfig {
+  pub query_timeout_secs: u64,
+  /// Seconds between two bootstraps once a bootstrap node is reachable.
+  pub bootstrap_interval_secs: u64,
+  /// Upper bound of the exponential backoff while no bootstrap node is reachable.
+  pub bootstrap_max_backoff_secs: u64,
+}
+
+impl Default for KademliaConfig {
+  fn default() -> Self {
+    KademliaConfig {
+      query_timeout_secs: 5 * 60,
+      bootstrap_interval_secs: 5 * 60,
+      bootstrap_max_backoff_secs: 5 * 60,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdentityConfig {
+  pub keystore: PathBuf,
+  pub nickname: Option<String>,
+}
+
+impl Default for IdentityConfig {
+  fn default() -> Self {
+    IdentityConfig {
+      keystore: keystore::default_path(),
+      nickname: None,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct UiConfig {
+  /// Do not print fallback logs.
+  pub silent: bool,
+  /// Full-screen terminal interface instead of printed lines.
+  pub tui: bool,
+  /// JSON commands on stdin and JSON events on stdout, for scripts.
+  pub json: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+  /// Address of the HTTP health and metrics endpoints of `serve`.
+  pub health_addr: String,
+  /// Act as a circuit relay for peers behind a NAT, in `serve` or chat mode.
+  pub relay: bool,
+}
+
+impl Default for ServerConfig {
+  fn default() -> Self {
+    ServerConfig {
+      health_addr: "0.0.0.0:8081".to_string(),
+      relay: false,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+  /// Serve the HTTP control API, with its `/events` WebSocket.
+  pub http: bool,
+  /// Address of the HTTP control API. It has no authentication, keep it on localhost.
+  pub http_addr: String,
+}
+
+impl Default for ApiConfig {
+  fn default() -> Self {
+    ApiConfig {
+      http: false,
+      http_addr: "127.0.0.1:8082".to_string(),
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+  /// Store the messages of the rooms on disk.
+  pub enabled: bool,
+  /// Database file, next to the keystore when unset.
+  pub path: Option<PathBuf>,
+  /// Messages kept per room, `0` for no limit.
+  pub max_messages: u64,
+  /// Days a message is kept, `0` for no limit.
+  pub max_age_days: u64,
+}
+
+impl Default for HistoryConfig {
+  fn default() -> Self {
+    HistoryConfig {
+      enabled: true,
+      path: None,
+      max_messages: 10_000,
+      max_age_days: 30,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeersConfig {
+  /// File of the peers blocked with `/block`, next to the keystore when unset.
+  pub blocklist: Option<PathBuf>,
+  /// Only connect to the `allowed` peers and the bootstrap nodes, for closed deployments.
+  pub allowlist_only: bool,
+  /// PeerIds allowed in `allowlist_only` mode.
+  #[serde(deserialize_with = "one_or_many")]
+  pub allowed: Vec<String>,
+}
+
+impl HistoryConfig {
+  pub fn retention(&self) -> Retention {
+    Retention {
+      max_messages: (self.max_messages > 0).then_some(self.max_messages),
+      max_age: (self.max_age_days > 0).then(|| Duration::from_secs(self.max_age_days * 24 * 3600)),
+    }
+  }
+}
+
+impl NetworkConfig {
+  /// The WebSocket listener, if enabled.
+  pub fn ws_listener(&self) -> Result<Option<WsListener>, Box<dyn Error>> {
+    let Some(port) = self.ws_port else {
+      return Ok(None);
+    };
+    let tls = match (&self.ws_cert, &self.ws_key) {
+      (Some(cert), Some(key)) => Some(ws::load_tls(cert, key)?),
+      (None, None) => None,
+      _ => return Err("Both network.ws_cert and network.ws_key are needed for /wss.".into()),
+    };
+    Ok(Some(WsListener { port, tls }))
+  }
+}
+
+impl Config {
+  /// Defaults, overridden by the file at `path` then by `DESNET_*` environment variables.
+  ///
+  /// A missing file is only an error when `required` is set.
+  pub fn load(path: &Path, required: bool) -> Result<Self, Box<dyn Error>> {
+    Config::load_with_vars(path, required, std::env::vars())
+  }
+
+  /// As [`Config::load`], with the given variables instead of the environment.
+  pub fn load_with_vars(
+    path: &Path,
+    required: bool,
+    vars: impl IntoIterator<Item = (String, String)>,
+  ) -> Result<Self, Box<dyn Error>> {
+    let mut value = toml::Value::try_from(Config::default())?;
+    match std::fs::read_to_string(path) {
+      Ok(content) => {
+        let file: toml::Table = content
+          .parse()
+          .map_err(|er| format!("Invalid config {}: {er}", path.display()))?;
+        merge(&mut value, toml::Value::Table(file));
+      }
+      Err(er) if required || er.kind() != std::io::ErrorKind::NotFound => {
+        return Err(format!("Cannot read config {}: {er}", path.display()).into());
+      }
+      Err(_) => {}
+    }
+    let (overrides, ignored_vars) = env_overrides(&value, vars.into_iter());
+    merge(&mut value, overrides);
+    let config = value
+      .try_into()
+      .map_err(|er| format!("Invalid config {}: {er}", path.display()))?;
+    Ok(Config {
+      ignored_vars,
+      ..config
+    })
+  }
+
+  /// The history database, `identity.history` next to `identity.key` by default, so that
+  /// nodes with their own keystore do not share it.
+  pub fn history_path(&self) -> PathBuf {
+    self
+      .history
+      .path
+      .clone()
+      .unwrap_or_else(|| self.identity.keystore.with_extension("history"))
+  }
+
+  /// The blocklist, `identity.blocked` next to `identity.key` by default.
+  pub fn blocklist_path(&self) -> PathBuf {
+    self
+      .peers
+      .blocklist
+      .clone()
+      .unwrap_or_else(|| self.identity.keystore.with_extension("blocked"))
+  }
+
+  /// The effective configuration, as TOML.
+  pub fn to_toml(&self) -> String {
+    toml::to_string_pretty(self).expect("Config is always serializable")
+  }
+}
+
+/// Accept a single string where a list is expected, as in `DESNET_NETWORK_BOOTSTRAP=/dns/...`.
+fn one_or_many<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+  #[derive(Deserialize)]
+  #[serde(untagged)]
+  enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+  }
+  Ok(match OneOrMany::deserialize(deserializer)? {
+    OneOrMany::One(value) => vec![value],
+    OneOrMany::Many(values) => values,
+  })
+}
+
+/// Recursively replace the values of `base` by the ones of `other`.
+fn merge(base: &mut toml::Value, other: toml::Value) {
+  match (base, other) {
+    (toml::Value::Table(base), toml::Value::Table(other)) => {
+      for (key, value) in other {
+        match base.get_mut(&key) {
+          Some(existing) => merge(existing, value),
+          None => {
+            base.insert(key, value);
+          }
+        }
+      }
+    }
+    (base, other) => *base = other,
+  }
+}
+
+/// Turn `DESNET_<SECTION>_<KEY>` variables into a table, along with the names of the unknown or
+/// invalid ones. Values are parsed as TOML and fall back to plain strings when that does not fit the key.
+fn env_overrides(
+  config: &toml::Value,
+  vars: impl Iterator<Item = (String, String)>,
+) -> (toml::Value, Vec<String>) {
+  let mut overrides = toml::Table::new();
+  let mut ignored = Vec::new();
+  for (name, raw) in vars {
+    let Some(name) = name.strip_prefix(ENV_PREFIX) else {
+      continue;
+    };
+    let name = name.to_lowercase();
+    let Some((section, key)) = name.split_once('_') else {
+      continue;
+    };
+    // Not a setting, as `DESNET_CONFIG` or `DESNET_PASSPHRASE`
+    if config.get(section).is_none() {
+      continue;
+    }
+    let parsed = format!("v = {raw}")
+      .parse::<toml::Table>()
+      .ok()
+      .and_then(|mut t| t.remove("v"));
+    // `2024` or `true` are also valid strings, as for `gossipsub.room`. Keys unset by default,
+    // as `history.path`, are only known by trying them.
+    let mut candidates: Vec<_> = parsed
+      .into_iter()
+      .chain([toml::Value::String(raw)])
+      .collect();
+    let accepted = candidates
+      .iter()
+      .position(|value| is_accepted(config, section, key, value));
+    let value = match accepted {
+      Some(index) => candidates.swap_remove(index),
+      // Keep invalid values of known keys, for loading to tell what is wrong with them
+      None if config.get(section).and_then(|s| s.get(key)).is_some() => candidates.swap_remove(0),
+      None => {
+        ignored.push(format!("{ENV_PREFIX}{}", name.to_uppercase()));
+        continue;
+      }
+    };
+    overrides
+      .entry(section)
+      .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+      .as_table_mut()
+      .expect("Sections are tables")
+      .insert(key.to_string(), value);
+  }
+  (toml::Value::Table(overrides), ignored)
+}
+
+/// Whether the configuration still loads once `section.key` is set to `value`.
+fn is_accepted(config: &toml::Value, section: &str, key: &str, value: &toml::Value) -> bool {
+  let mut config = config.clone();
+  if let Some(section) = config.get_mut(section).and_then(toml::Value::as_table_mut) {
+    section.insert(key.to_string(), value.clone());
+  }
+  config.try_into::<Config>().is_ok()
+}
@@ -0,0 +1,415 @@
+use futures::{SinkExt, StreamExt};
+use libp2p::metrics::Registry;
+use serde::Deserialize;
+use std::{
+  io,
+  net::{IpAddr, SocketAddr},
+  sync::Arc,
+  time::Duration,
+};
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::{TcpListener, TcpStream},
+  select,
+  sync::{
+    broadcast::{self, error::RecvError},
+    mpsc, oneshot,
+  },
+  time::timeout,
+};
+use tokio_tungstenite::{
+  tungstenite::{handshake::derive_accept_key, protocol::Role, Message},
+  WebSocketStream,
+};
+
+use crate::{
+  metrics::{self, METRICS_PATH},
+  utils::api::{ApiCommand, ApiEvent, ApiRequest, ApiResult},
+};
+
+/// A command of the HTTP API, to be executed by the loop driving the [`crate::ChatNode`], and
+/// where to send its result.
+pub type Call = (ApiRequest, oneshot::Sender<ApiResult>);
+
+/// Largest request accepted, headers and body included.
+const MAX_REQUEST: usize = 64 * 1024;
+/// Time given to a client to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// Events kept for the slowest WebSocket client, older ones are dropped.
+pub const EVENT_BUFFER: usize = 1_024;
+
+const JSON: &str = "application/json";
+
+const OK: &str = "200 OK";
+const BAD_REQUEST: &str = "400 Bad Request";
+const FORBIDDEN: &str = "403 Forbidden";
+const NOT_FOUND: &str = "404 Not Found";
+const METHOD_NOT_ALLOWED: &str = "405 Method Not Allowed";
+const REQUEST_TIMEOUT: &str = "408 Request Timeout";
+const UNSUPPORTED_MEDIA_TYPE: &str = "415 Unsupported Media Type";
+const UPGRADE_REQUIRED: &str = "426 Upgrade Required";
+const UNAVAILABLE: &str = "503 Service Unavailable";
+
+/// Body of `POST /rooms/<room>/messages`.
+#[derive(Deserialize)]
+struct Post {
+  text: String,
+}
+
+struct Request {
+  method: String,
+  path: String,
+  query: String,
+  /// Names and values, trimmed.
+  headers: Vec<(String, String)>,
+  body: Vec<u8>,
+}
+
+impl Request {
+  /// Value of the first header of that name, ignoring case.
+  fn header(&self, name: &str) -> Option<&str> {
+    self
+      .headers
+      .iter()
+      .find(|(key, _)| key.eq_ignore_ascii_case(name))
+      .map(|(_, value)| value.as_str())
+  }
+
+  /// The `Sec-WebSocket-Key` of a WebSocket upgrade to the version we speak.
+  fn websocket_key(&self) -> Option<&str> {
+    let upgrade = self
+      .header("upgrade")
+      .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    let version = self.header("sec-websocket-version") == Some("13");
+    self
+      .header("sec-websocket-key")
+      .filter(|_| upgrade && version)
+  }
+
+  /// Whether the request comes from a page served by this machine, or from no page at all.
+  /// Browsers do not apply CORS to WebSockets, any site could stream our events otherwise.
+  fn is_local_origin(&self) -> bool {
+    let Some(origin) = self.header("origin") else {
+      return true;
+    };
+    let Some((_, host)) = origin.split_once("://") else {
+      return false;
+    };
+    let (name, _) = split_host(host);
+    match name.parse::<IpAddr>() {
+      Ok(ip) => ip.is_loopback(),
+      Err(_) => name.eq_ignore_ascii_case("localhost"),
+    }
+  }
+
+  /// Whether the `Host` is ours, addressed by IP or as `localhost`. Pages of other sites
+  /// resolving their own name to our address (DNS rebinding) are refused.
+  fn is_local_host(&self, local_addr: SocketAddr) -> bool {
+    let Some(host) = self.header("host") else {
+      return false;
+    };
+    let (name, port) = split_host(host);
+    if port.is_some_and(|port| port.parse() != Ok(local_addr.port())) {
+      return false;
+    }
+    match name.parse::<IpAddr>() {
+      Ok(ip) => ip == local_addr.ip() || (ip.is_loopback() && local_addr.ip().is_loopback()),
+      Err(_) => name.eq_ignore_ascii_case("localhost") && local_addr.ip().is_loopback(),
+    }
+  }
+}
+
+/// Split `name:port`, `[ipv6]:port` or a host without port, brackets removed.
+fn split_host(host: &str) -> (&str, Option<&str>) {
+  let (name, port) = match host.rsplit_once(':') {
+    Some((name, port)) if !port.contains(']') => (name, Some(port)),
+    _ => (host, None),
+  };
+  (name.trim_start_matches('[').trim_end_matches(']'), port)
+}
+
+/// Answer the HTTP API, sending the commands on `calls`:
+///
+/// - `GET /node`: our PeerId, nickname and listen addresses
+/// - `GET /peers`: the connected peers
+/// - `GET /rooms`: the joined rooms
+/// - `GET /rooms/<room>/messages?limit=<n>`: the last messages of a room
+/// - `POST /rooms/<room>/messages` with `{"text": "..."}`: send a message to a room
+/// - `GET /events`: a WebSocket streaming the `events`, and taking commands as in JSON mode
+/// - `GET /metrics`: the Prometheus `metrics`
+///
+/// Requests must name the address of the listener, or `localhost`, as their `Host`.
+pub async fn serve(
+  listener: TcpListener,
+  calls: mpsc::Sender<Call>,
+  events: broadcast::Sender<ApiEvent>,
+  metrics: Arc<Registry>,
+) {
+  loop {
+    match listener.accept().await {
+      Ok((stream, _)) => {
+        let calls = calls.clone();
+        let events = events.subscribe();
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+          if let Err(er) = respond(stream, calls, events, &metrics).await {
+            tracing::warn!("Failed to answer an API request: {er}");
+          }
+        });
+      }
+      Err(er) => tracing::warn!("Failed to accept an API request: {er}"),
+    }
+  }
+}
+
+async fn respond(
+  mut stream: TcpStream,
+  calls: mpsc::Sender<Call>,
+  events: broadcast::Receiver<ApiEvent>,
+  metrics: &Registry,
+) -> io::Result<()> {
+  let Ok(request) = timeout(READ_TIMEOUT, read_request(&mut stream)).await else {
+    let result = ApiResult::failed(None, "request timeout");
+    return write_result(stream, REQUEST_TIMEOUT, result).await;
+  };
+  let Some(request) = request? else {
+    let result = ApiResult::failed(None, "malformed request");
+    return write_result(stream, BAD_REQUEST, result).await;
+  };
+  if !request.is_local_host(stream.local_addr()?) {
+    let result = ApiResult::failed(None, "unexpected Host");
+    return write_result(stream, FORBIDDEN, result).await;
+  }
+  if request.method == "GET" && request.path == METRICS_PATH {
+    let body = metrics::encode(metrics);
+    return write_response(stream, OK, metrics::CONTENT_TYPE, &body).await;
+  }
+  if request.path.trim_matches('/') == "events" {
+    if request.method != "GET" {
+      let result = ApiResult::failed(None, "method not allowed");
+      return write_result(stream, METHOD_NOT_ALLOWED, result).await;
+    }
+    let Some(key) = request.websocket_key() else {
+      let result = ApiResult::failed(None, "expected a WebSocket upgrade, version 13");
+      return write_result(stream, UPGRADE_REQUIRED, result).await;
+    };
+    if !request.is_local_origin() {
+      let result = ApiResult::failed(None, "unexpected Origin");
+      return write_result(stream, FORBIDDEN, result).await;
+    }
+    let key = key.to_string();
+    return stream_events(stream, &key, calls, events).await;
+  }
+  let (status, result) = match route(&request) {
+    Ok(command) => call(&calls, ApiRequest { id: None, command }).await,
+    Err((status, error)) => (status, ApiResult::failed(None, error)),
+  };
+  write_result(stream, status, result).await
+}
+
+async fn write_result(stream: TcpStream, status: &str, result: ApiResult) -> io::Result<()> {
+  let body = serde_json::to_string(&result).expect("ApiResult is always serializable");
+  write_response(stream, status, JSON, &body).await
+}
+
+async fn write_response(
+  mut stream: TcpStream,
+  status: &str,
+  content_type: &str,
+  body: &str,
+) -> io::Result<()> {
+  let response = format!(
+    "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+    body.len()
+  );
+  stream.write_all(response.as_bytes()).await?;
+  stream.shutdown().await
+}
+
+/// Accept the WebSocket, then send it the events and the results of its commands until closed.
+async fn stream_events(
+  mut stream: TcpStream,
+  key: &str,
+  calls: mpsc::Sender<Call>,
+  mut events: broadcast::Receiver<ApiEvent>,
+) -> io::Result<()> {
+  let response = format!(
+    "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+    derive_accept_key(key.as_bytes())
+  );
+  stream.write_all(response.as_bytes()).await?;
+  let mut socket = WebSocketStream::from_raw_socket(stream, Role::Server, None).await;
+  loop {
+    let event = select! {
+      message = socket.next() => match message {
+        Some(Ok(Message::Text(text))) => match serde_json::from_str::<ApiRequest>(&text) {
+          Ok(request) => ApiEvent::Result(call(&calls, request).await.1),
+          Err(er) => ApiEvent::Result(ApiResult::failed(None, format!("Invalid command: {er}"))),
+        },
+        Some(Ok(Message::Close(_))) | None => return Ok(()),
+        // Pings are answered by tungstenite
+        Some(Ok(_)) => continue,
+        Some(Err(er)) => return Err(io::Error::other(er)),
+      },
+      event = events.recv() => match event {
+        Ok(event) => event,
+        Err(RecvError::Lagged(missed)) => {
+          tracing::warn!("A WebSocket client is too slow, {missed} events dropped");
+          continue;
+        }
+        Err(RecvError::Closed) => return Ok(()),
+      },
+    };
+    let text = serde_json::to_string(&event).expect("ApiEvent is always serializable");
+    socket
+      .send(Message::Text(text))
+      .await
+      .map_err(io::Error::other)?;
+  }
+}
+
+/// Read the request line, the headers, and a body of `Content-Length` bytes.
+async fn read_request(stream: &mut TcpStream) -> io::Result<Option<Request>> {
+  let mut buf = Vec::new();
+  let mut chunk = [0u8; 4096];
+  let head_len = loop {
+    if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+      break pos + 4;
+    }
+    let len = stream.read(&mut chunk).await?;
+    if len == 0 || buf.len() + len > MAX_REQUEST {
+      return Ok(None);
+    }
+    buf.extend_from_slice(&chunk[..len]);
+  };
+  let head = String::from_utf8_lossy(&buf[..head_len]).into_owned();
+  let mut lines = head.split("\r\n");
+  let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+  let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+    return Ok(None);
+  };
+  let headers = lines
+    .filter_map(|line| line.split_once(':'))
+    .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+    .collect::<Vec<_>>();
+  let content_length = headers
+    .iter()
+    .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+    .map(|(_, value)| value.parse::<usize>())
+    .unwrap_or(Ok(0));
+  let Ok(content_length) = content_length else {
+    return Ok(None);
+  };
+  if head_len + content_length > MAX_REQUEST {
+    return Ok(None);
+  }
+  let mut body = buf.split_off(head_len);
+  while body.len() < content_length {
+    let len = stream.read(&mut chunk).await?;
+    if len == 0 {
+      return Ok(None);
+    }
+    body.extend_from_slice(&chunk[..len]);
+  }
+  body.truncate(content_length);
+  let (path, query) = target.split_once('?').unwrap_or((target, ""));
+  Ok(Some(Request {
+    method: method.to_string(),
+    path: path.to_string(),
+    query: query.to_string(),
+    headers,
+    body,
+  }))
+}
+
+fn route(request: &Request) -> Result<ApiCommand, (&'static str, String)> {
+  let segments = request
+    .path
+    .trim_matches('/')
+    .split('/')
+    .map(percent_decode)
+    .collect::<Vec<_>>();
+  let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+  match (request.method.as_str(), segments.as_slice()) {
+    ("GET", ["node"]) => Ok(ApiCommand::Info),
+    ("GET", ["peers"]) => Ok(ApiCommand::Peers),
+    ("GET", ["rooms"]) => Ok(ApiCommand::Rooms),
+    ("GET", ["rooms", room, "messages"]) => {
+      let limit = query_param(&request.query, "limit")
+        .map(|limit| limit.parse())
+        .transpose()
+        .map_err(|_| (BAD_REQUEST, "limit must be a number".to_string()))?;
+      Ok(ApiCommand::History {
+        room: room.to_string(),
+        limit,
+      })
+    }
+    ("POST", ["rooms", room, "messages"]) => {
+      // Forms and simple requests of other sites cannot send JSON
+      let json = request
+        .header("content-type")
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case(JSON));
+      if !json {
+        let error = format!("expected Content-Type: {JSON}");
+        return Err((UNSUPPORTED_MEDIA_TYPE, error));
+      }
+      let post: Post = serde_json::from_slice(&request.body)
+        .map_err(|er| (BAD_REQUEST, format!("invalid body: {er}")))?;
+      Ok(ApiCommand::Publish {
+        room: room.to_string(),
+        text: post.text,
+      })
+    }
+    (_, ["node" | "peers" | "rooms"] | ["rooms", _, "messages"]) => {
+      Err((METHOD_NOT_ALLOWED, "method not allowed".to_string()))
+    }
+    _ => Err((NOT_FOUND, "not found".to_string())),
+  }
+}
+
+/// Hand the command over to the node, and wait for its result.
+async fn call(calls: &mpsc::Sender<Call>, request: ApiRequest) -> (&'static str, ApiResult) {
+  let (tx, rx) = oneshot::channel();
+  let id = request.id.clone();
+  if calls.send((request, tx)).await.is_err() {
+    return (UNAVAILABLE, ApiResult::failed(id, "node stopped"));
+  }
+  match rx.await {
+    Ok(result) if result.ok => (OK, result),
+    Ok(result) => (BAD_REQUEST, result),
+    Err(_) => (UNAVAILABLE, ApiResult::failed(id, "node stopped")),
+  }
+}
+
+fn query_param(query: &str, name: &str) -> Option<String> {
+  query
+    .split('&')
+    .filter_map(|pair| pair.split_once('='))
+    .find(|(key, _)| *key == name)
+    .map(|(_, value)| percent_decode(value))
+}
+
+/// Decode `%XX` escapes, so that room names may contain any character.
+fn percent_decode(input: &str) -> String {
+  let bytes = input.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let hex = bytes
+      .get(i + 1..i + 3)
+      .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+      .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+    match (bytes[i], hex) {
+      (b'%', Some(byte)) => {
+        decoded.push(byte);
+        i += 3;
+      }
+      (byte, _) => {
+        decoded.push(byte);
+        i += 1;
+      }
+    }
+  }
+  String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,8 @@
+pub mod config;
+pub mod control;
+pub mod metrics;
+pub mod node;
+pub mod server;
+pub mod utils;
+
+pub use node::{ChatEvent, ChatNode, ChatNodeBuilder};
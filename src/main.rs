@@ -1,207 +1,797 @@
-use clap::Parser;
-use futures::stream::StreamExt;
-use libp2p::{
-  autonat, gossipsub, identify,
-  identity::Keypair,
-  kad::{self, store, BootstrapOk, GetClosestPeersOk, Mode},
-  noise, ping,
-  swarm::{NetworkBehaviour, SwarmEvent},
-  tcp, yamux, SwarmBuilder,
+use chrono::{DateTime, Local};
+use clap::{Parser, Subcommand};
+use libp2p::{autonat::NatStatus, identity::Keypair, PeerId};
+use rust_libp2p_chat::{
+  config::{self, Config},
+  control::{self, Call},
+  metrics::METRICS_PATH,
+  node::Rejection,
+  server::{health, ServerEvent, ServerNode},
+  utils::{
+    api::{ApiEvent, ApiRequest, ApiResult},
+    cmd::Command,
+    history::HistoryRecord,
+    keystore,
+    msg::{Envelope, Kind},
+    peer::ed25519_from_seed,
+  },
+  ChatEvent, ChatNode, ChatNodeBuilder,
+};
+use std::{error::Error, path::PathBuf};
+use tokio::{
+  io,
+  io::AsyncBufReadExt,
+  net::TcpListener,
+  select,
+  sync::{broadcast, mpsc},
 };
-use std::{error::Error, time::Duration};
-use tokio::{io, io::AsyncBufReadExt, select};
 use tracing_subscriber::EnvFilter;
 
-pub mod utils;
+mod tui;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-  /// If seed is provided, it will be used to create the keypair.
+  #[command(subcommand)]
+  action: Option<Action>,
+  /// Configuration file. Its values are overridden by DESNET_<SECTION>_<KEY> variables and flags.
+  #[arg(short, long, global = true, env = "DESNET_CONFIG")]
+  config: Option<PathBuf>,
+  /// Keystore holding the node identity. Set DESNET_PASSPHRASE to encrypt or decrypt it.
+  #[arg(short, long, global = true)]
+  keystore: Option<PathBuf>,
+  /// Start with a bootstrap node, can be repeated. If not provided, the current node will become a bootstrap node.
+  #[arg(short, long, global = true)]
+  bootstrap: Vec<String>,
+  /// Port.
+  #[arg(short, long, global = true)]
+  port: Option<u16>,
+  /// Also listen on WebSocket for browsers, on this port. Set network.ws_cert and network.ws_key for /wss.
+  #[arg(long, global = true)]
+  ws_port: Option<u16>,
+  /// Room to join on start.
   #[arg(short, long)]
-  seed: Option<String>,
-  /// Start with a bootstrap node. If not provided, the current node will become a bootstrap node.
+  room: Option<String>,
+  /// Nickname shown to others.
   #[arg(short, long)]
-  bootstrap: Option<String>,
-  /// Port.
-  #[arg(short, long, default_value_t = 0)]
-  port: u16,
+  nick: Option<String>,
+  /// Discover peers on the local network, no bootstrap node needed.
+  #[arg(long, default_value_t = false)]
+  mdns: bool,
   /// Do not print fallback logs.
   #[arg(long, default_value_t = false)]
   silent: bool,
+  /// Full-screen terminal interface, with rooms, peers and network events in their own panes.
+  #[arg(long, default_value_t = false)]
+  tui: bool,
+  /// Read JSON commands from stdin and write JSON events to stdout, one per line. Logs go to stderr.
+  #[arg(long, default_value_t = false, conflicts_with = "tui")]
+  json: bool,
+  /// Serve the HTTP control API and its event WebSocket on api.http_addr (127.0.0.1:8082 by default).
+  #[arg(long, default_value_t = false)]
+  http: bool,
+}
+
+impl Args {
+  /// Load the configuration file and apply the flags on top of it.
+  fn config(&self) -> Result<Config, Box<dyn Error>> {
+    let mut config = match &self.config {
+      Some(path) => Config::load(path, true)?,
+      None => Config::load(&config::default_path(), false)?,
+    };
+    if let Some(keystore) = &self.keystore {
+      config.identity.keystore = keystore.clone();
+    }
+    if !self.bootstrap.is_empty() {
+      config.network.bootstrap = self.bootstrap.clone();
+    }
+    if let Some(port) = self.port {
+      config.network.port = port;
+    }
+    if let Some(port) = self.ws_port {
+      config.network.ws_port = Some(port);
+    }
+    if let Some(room) = &self.room {
+      config.gossipsub.room = room.clone();
+    }
+    if let Some(nick) = &self.nick {
+      config.identity.nickname = Some(nick.clone());
+    }
+    if self.mdns {
+      config.network.mdns = true;
+    }
+    if self.silent {
+      config.ui.silent = true;
+    }
+    if self.tui {
+      config.ui.tui = true;
+    }
+    if self.json {
+      config.ui.json = true;
+    }
+    if self.http {
+      config.api.http = true;
+    }
+    if let Some(Action::Serve { health, relay }) = &self.action {
+      if let Some(health) = health {
+        config.server.health_addr = health.clone();
+      }
+      if *relay {
+        config.server.relay = true;
+      }
+    }
+    Ok(config)
+  }
+}
+
+#[derive(Subcommand, Debug)]
+enum Action {
+  /// Create a new identity in the keystore.
+  Keygen {
+    /// Replace the existing identity.
+    #[arg(long, default_value_t = false)]
+    force: bool,
+  },
+  /// Write the identity, unencrypted, to a file to be imported elsewhere.
+  Export {
+    /// Destination file, it must not exist.
+    out: PathBuf,
+  },
+  /// Store an identity written by `export` into the keystore.
+  Import {
+    /// File written by `export`.
+    #[arg(required_unless_present = "from_seed")]
+    file: Option<PathBuf>,
+    /// Derive the identity from a seed read on stdin, as former versions did with `--seed`.
+    #[arg(long, default_value_t = false, conflicts_with = "file")]
+    from_seed: bool,
+    /// Replace the existing identity.
+    #[arg(long, default_value_t = false)]
+    force: bool,
+  },
+  /// Run a bootstrap node: DHT, identify and AutoNAT only, without chat.
+  Serve {
+    /// Address of the HTTP health and metrics endpoints.
+    #[arg(long)]
+    health: Option<String>,
+    /// Relay connections for peers behind a NAT.
+    #[arg(long, default_value_t = false)]
+    relay: bool,
+  },
+  /// Inspect the configuration.
+  #[command(subcommand)]
+  Config(ConfigAction),
 }
 
-#[derive(NetworkBehaviour)]
-struct MyBehaviour {
-  ping: ping::Behaviour, // To keep the fly alive when connecting
-  identify: identify::Behaviour,
-  kademlia: kad::Behaviour<store::MemoryStore>,
-  autonat: autonat::Behaviour,
-  gossipsub: gossipsub::Behaviour,
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+  /// Print the effective configuration, once the file, environment and flags are merged.
+  Print,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-  use utils::{
-    msg::message_id,
-    peer::{ed25519_from_seed, parse_peer_id},
-  };
-
-  let Args {
-    seed,
-    bootstrap,
-    port,
-    silent,
-  } = Args::parse();
-
-  // Create a random key for ourselves & read user's inputs
-  let keypair = if let Some(s) = seed {
-    ed25519_from_seed(&s)?
-  } else {
-    Keypair::generate_ed25519()
-  };
+  let mut args = Args::parse();
+  let config = args.config()?;
+  // On stderr, as logs are not set up yet and stdout only has events in JSON mode
+  for name in &config.ignored_vars {
+    eprintln!("⚠️ Ignored {name}: unknown setting or invalid value");
+  }
+  let passphrase = std::env::var(keystore::PASSPHRASE_ENV).ok();
+  let passphrase = passphrase.as_deref();
 
-  let _ = tracing_subscriber::fmt()
-    .with_env_filter(EnvFilter::from_default_env())
-    .try_init();
+  if let Some(action) = args.action.take() {
+    return run_action(action, &config, passphrase).await;
+  }
 
-  let mut swarm = SwarmBuilder::with_existing_identity(keypair.clone())
-    .with_tokio()
-    .with_tcp(
-      tcp::Config::default(),
-      noise::Config::new,
-      yamux::Config::default,
-    )?
-    .with_dns()?
-    .with_behaviour(|key| {
-      // Create a Ping behaviour
-      let ping = ping::Behaviour::default();
-      // Create a Identify behaviour.
-      let identify = identify::Behaviour::new(identify::Config::new(
-        "/ipfs/id/1.0.0".to_string(),
-        keypair.public(),
-      ));
-      // Create a Kademlia behaviour.
-      let mut cfg = kad::Config::default();
-      cfg.set_query_timeout(Duration::from_secs(5 * 60));
-      let store = store::MemoryStore::new(key.public().to_peer_id());
-      let kademlia = kad::Behaviour::with_config(key.public().to_peer_id(), store, cfg);
-      // Create a AutoNAT behaviour.
-      let autonat = autonat::Behaviour::new(key.public().to_peer_id(), Default::default());
-      // Create a Gossipsub behaviour.
-      let gossipsub = gossipsub::Behaviour::new(
-        gossipsub::MessageAuthenticity::Signed(key.clone()),
-        gossipsub::ConfigBuilder::default()
-          .heartbeat_interval(Duration::from_secs(10))
-          .validation_mode(gossipsub::ValidationMode::Strict)
-          .message_id_fn(message_id)
-          .build()?,
-      )?;
-      // Return my behavour
-      Ok(MyBehaviour {
-        ping,
-        identify,
-        kademlia,
-        autonat,
-        gossipsub,
-      })
-    })?
-    .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(3600))) // Disconnected after 1 hour idle
-    .build();
+  // Load our key & read user's inputs
+  let logs = config.ui.tui.then(tui::capture_logs);
+  if config.ui.json {
+    // Keep stdout for the events
+    let _ = tracing_subscriber::fmt()
+      .with_env_filter(EnvFilter::from_default_env())
+      .with_writer(std::io::stderr)
+      .try_init();
+  }
+  let keypair = load_identity(&config, passphrase)?;
 
-  // Peer node: Listen on all interfaces and whatever port the OS assigns
-  swarm.behaviour_mut().kademlia.set_mode(Some(Mode::Server));
-  swarm.listen_on(format!("/ip4/0.0.0.0/tcp/{port}").parse()?)?;
+  let node = ChatNodeBuilder::from_config(&config)?
+    .keypair(keypair)
+    .build()?;
+  let current = Some(config.gossipsub.room.clone());
 
-  if let Some(bootstrap_addr) = bootstrap {
-    // Add peers to the DHT
-    swarm
-      .behaviour_mut()
-      .kademlia
-      .add_address(&parse_peer_id(&bootstrap_addr)?, bootstrap_addr.parse()?);
-    // Bootstrap the connection
-    if let Err(e) = swarm.behaviour_mut().kademlia.bootstrap() {
-      println!("⛔️ Failed to run Kademlia bootstrap: {e:?}");
+  // Commands of the HTTP API, none when it is disabled, and the events of its WebSockets
+  let (calls, api) = mpsc::channel(16);
+  let (events, _) = broadcast::channel(control::EVENT_BUFFER);
+  if config.api.http {
+    let listener = TcpListener::bind(&config.api.http_addr).await?;
+    let notice = format!("🌐 HTTP API on http://{}", listener.local_addr()?);
+    if config.ui.json {
+      eprintln!("{notice}");
+    } else {
+      println!("{notice}");
     }
+    tokio::spawn(control::serve(
+      listener,
+      calls,
+      events.clone(),
+      node.metrics(),
+    ));
+  }
+
+  match logs {
+    Some(logs) => tui::run(node, current, config.ui.silent, logs, api, events).await,
+    None if config.ui.json => json(node, api, events).await,
+    None => chat(node, current, config.ui.silent, api, events).await,
   }
+}
 
+/// Read JSON commands from stdin and write JSON events to stdout, one per line.
+async fn json(
+  mut node: ChatNode,
+  mut api: mpsc::Receiver<Call>,
+  events: broadcast::Sender<ApiEvent>,
+) -> Result<(), Box<dyn Error>> {
+  let mut stdin = io::BufReader::new(io::stdin()).lines();
+  // Listening only scripts may close stdin
+  let mut reading = true;
+  loop {
+    let event = select! {
+      line = stdin.next_line(), if reading => match line? {
+        Some(line) if line.trim().is_empty() => continue,
+        Some(line) => match serde_json::from_str::<ApiRequest>(&line) {
+          Ok(request) => ApiEvent::Result(node.execute(request)),
+          Err(er) => ApiEvent::Result(ApiResult::failed(None, format!("Invalid command: {er}"))),
+        },
+        None => {
+          reading = false;
+          continue;
+        }
+      },
+      Some((request, reply)) = api.recv() => {
+        // The HTTP client may be gone
+        let _ = reply.send(node.execute(request));
+        continue;
+      }
+      event = node.next_event() => match node.api_event(&event) {
+        Some(event) => {
+          // None may be listening
+          let _ = events.send(event.clone());
+          event
+        }
+        None => continue,
+      },
+    };
+    println!("{}", serde_json::to_string(&event)?);
+  }
+}
+
+/// Read commands from stdin and print the events, one per line.
+async fn chat(
+  mut node: ChatNode,
+  mut current: Option<String>,
+  silent: bool,
+  mut api: mpsc::Receiver<Call>,
+  events: broadcast::Sender<ApiEvent>,
+) -> Result<(), Box<dyn Error>> {
   // Read full lines from stdin
   let mut stdin = io::BufReader::new(io::stdin()).lines();
-  let topic = gossipsub::IdentTopic::new("desnet-the-room");
-  swarm.behaviour_mut().gossipsub.subscribe(&topic)?;
-  println!("💻 Type to send messages to others here:");
+  println!(
+    "💻 Type to send messages to others here (/join, /leave, /switch, /rooms, /nick, /msg, /private, /invite, /kick, /decline, /block, /unblock, /blocked, /history):"
+  );
 
   // Kick it off
   loop {
     select! {
-      Ok(Some(msg)) = stdin.next_line() => {
-        // Publish messages
-        if let Err(er)=  swarm.behaviour_mut().gossipsub.publish(topic.clone(), msg.as_bytes()){
-          println!("❌ Failed to publish the message: {er}");
-        } else {
-          println!("🛫 .................. Sent");
+      Ok(Some(line)) = stdin.next_line() => match Command::parse(&line) {
+        Ok(cmd) => {
+          for reply in handle_command(&mut node, &mut current, cmd) {
+            println!("{reply}");
+          }
+        }
+        Err(er) => println!("❌ {er}"),
+      },
+      event = node.next_event() => {
+        broadcast_event(&node, &events, &event);
+        for line in event_lines(&node, event, silent) {
+          println!("{}", line.text());
         }
       }
-      event = swarm.select_next_some() => match event {
-        SwarmEvent::NewListenAddr { address, .. } => {
-          let mut addr = String::from("");
-          addr.push_str(&address.to_string());
-          addr.push_str("/p2p/");
-          addr.push_str(&keypair.public().to_peer_id().to_string());
-          println!("✅ Local node is listening on {addr}");
+      Some((request, reply)) = api.recv() => {
+        // The HTTP client may be gone
+        let _ = reply.send(node.execute(request));
+      }
+    }
+  }
+}
+
+/// Send the event to the WebSocket clients of the HTTP API, if there are any.
+fn broadcast_event(node: &ChatNode, events: &broadcast::Sender<ApiEvent>, event: &ChatEvent) {
+  if events.receiver_count() == 0 {
+    return;
+  }
+  if let Some(event) = node.api_event(event) {
+    let _ = events.send(event);
+  }
+}
+
+/// Text shown for an event, and where it belongs.
+enum Line {
+  /// A message of a room.
+  Room(String, String),
+  /// A direct message, or what happened to one.
+  Chat(String),
+  /// Connections, discovery and the other network events.
+  Status(String),
+}
+
+impl Line {
+  fn text(&self) -> &str {
+    match self {
+      Line::Room(_, text) | Line::Chat(text) | Line::Status(text) => text,
+    }
+  }
+}
+
+fn event_lines(node: &ChatNode, event: ChatEvent, silent: bool) -> Vec<Line> {
+  let line = match event {
+    ChatEvent::ListenAddr(addr) => Line::Status(format!("✅ Local node is listening on {addr}")),
+    ChatEvent::IncomingConnection(send_back_addr) => {
+      Line::Status(format!("⏳ Connecting to {send_back_addr}"))
+    }
+    ChatEvent::PeerConnected { peer_id, path } => {
+      Line::Status(format!("🔗 Connected to {peer_id} {path}"))
+    }
+    ChatEvent::PeerDisconnected(peer_id) => Line::Status(format!("💔 Disconnected to {peer_id}")),
+    ChatEvent::PeerIdentified(peer_id) => Line::Status(format!("👤 Identify new peer: {peer_id}")),
+    ChatEvent::NatStatusChanged(status) => Line::Status(match status {
+      NatStatus::Public(addr) => format!("🌐 Reachable from the outside at {addr}"),
+      NatStatus::Private => "🧱 Behind a NAT, looking for a relay to be reachable".to_string(),
+      NatStatus::Unknown => "🌫️  Reachability unknown".to_string(),
+    }),
+    ChatEvent::RelayReserved { relay } => Line::Status(format!(
+      "📡 Reachable through relay {relay}, peers will try to punch a direct path"
+    )),
+    ChatEvent::HolePunched { peer_id, result } => Line::Status(match result {
+      Ok(()) => format!("🕳️  Hole punched, now directly connected to {peer_id}"),
+      Err(er) => format!("🔁 Staying relayed to {peer_id}, hole punching failed: {er}"),
+    }),
+    ChatEvent::Bootstrapped(peer_id) => {
+      Line::Status(format!("🚀 Kademlia bootstrapped completely: {peer_id:?}"))
+    }
+    ChatEvent::BootstrapStatus { reachable, total } => {
+      Line::Status(format!("🧭 Bootstrap peers reachable: {reachable}/{total}"))
+    }
+    ChatEvent::BootstrapRetry { attempt, delay } => Line::Status(format!(
+      "🔁 No bootstrap peer reachable, retrying (attempt {attempt}, next in {delay:?})"
+    )),
+    ChatEvent::LocalPeersDiscovered(peers) => {
+      Line::Status(format!("🏠 Found peers on the local network: {peers:?}"))
+    }
+    ChatEvent::PeersDiscovered(peers) => {
+      Line::Status(format!("🔍 Kademlia discovered new peers: {peers:?}"))
+    }
+    ChatEvent::Message {
+      propagation_source,
+      message,
+      envelope,
+      ..
+    } => {
+      let room = message.topic;
+      // The propagation source may only be relaying the author's message
+      let author = message.source.unwrap_or(propagation_source);
+      let from = display_name(node, &author, &envelope);
+      let text = match envelope.kind {
+        Kind::Text => format!("💌 [{room}] Message from {from}: {}", envelope.body),
+        Kind::Encrypted => format!("🔐 [{room}] Undecryptable message from {from}"),
+        Kind::RoomKey | Kind::Unknown => {
+          format!("📦 [{room}] Unsupported message from {from}, please upgrade")
         }
-        SwarmEvent::IncomingConnection { send_back_addr, .. } => {
-          println!("⏳ Connecting to {send_back_addr}");
+      };
+      Line::Room(room.into_string(), text)
+    }
+    ChatEvent::DirectMessage { peer_id, envelope } => {
+      let from = display_name(node, &peer_id, &envelope);
+      Line::Chat(match envelope.kind {
+        Kind::Text => format!("📨 Direct message from {from}: {}", envelope.body),
+        _ => format!("📦 Unsupported direct message from {from}, please upgrade"),
+      })
+    }
+    ChatEvent::DirectDelivered { peer_id, .. } => {
+      Line::Chat(format!("🛬 .................. Delivered to {peer_id}"))
+    }
+    ChatEvent::DirectFailed { peer_id, error, .. } => Line::Chat(format!(
+      "❌ Failed to deliver the message to {peer_id}: {error}"
+    )),
+    ChatEvent::RoomKeyReceived {
+      room,
+      peer_id,
+      epoch,
+    } => Line::Chat(if node.is_joined(&room) {
+      format!("🔑 {peer_id} rotated the key of [{room}] (epoch {epoch})")
+    } else {
+      format!("🔑 {peer_id} invited you to [{room}], /join {room} to enter or /decline {room}")
+    }),
+    ChatEvent::MessageRejected {
+      propagation_source,
+      author,
+      room,
+      reason,
+    } => Line::Status(match reason {
+      Rejection::RateLimited => {
+        format!("🚫 {author} is over the rate limit in [{room}], dropping its messages")
+      }
+      reason => {
+        format!("🚫 Rejected a message of {author} in [{room}] from {propagation_source}: {reason}")
+      }
+    }),
+    ChatEvent::HistorySynced {
+      room,
+      peer_id,
+      records,
+    } => {
+      let header = format!(
+        "📜 {} missed messages of [{room}] from {peer_id}:",
+        records.len()
+      );
+      let mut lines = vec![Line::Room(room.clone(), header)];
+      for record in records {
+        lines.push(Line::Room(room.clone(), format_record(node, record)));
+      }
+      return lines;
+    }
+    ChatEvent::NicknameChanged { peer_id, nickname } => {
+      Line::Status(format!("🏷️  {peer_id} is now known as {nickname}"))
+    }
+    ChatEvent::Other(event) => {
+      if silent {
+        return Vec::new();
+      }
+      Line::Status(format!("❓ Other Behaviour events {event:?}"))
+    }
+  };
+  vec![line]
+}
+
+/// Load the identity from the keystore, creating it on first run, and set up the logs.
+fn load_identity(config: &Config, passphrase: Option<&str>) -> Result<Keypair, Box<dyn Error>> {
+  let keystore_path = &config.identity.keystore;
+  let (keypair, created) = keystore::load_or_create(keystore_path, passphrase)?;
+  if created {
+    let notice = format!("🔑 Created a new identity in {}", keystore_path.display());
+    // Only events are written to stdout in JSON mode
+    if config.ui.json {
+      eprintln!("{notice}");
+    } else {
+      println!("{notice}");
+    }
+  }
+
+  let _ = tracing_subscriber::fmt()
+    .with_env_filter(EnvFilter::from_default_env())
+    .try_init();
+  Ok(keypair)
+}
+
+/// Run a bootstrap node until SIGTERM or Ctrl-C.
+async fn serve(config: &Config, passphrase: Option<&str>) -> Result<(), Box<dyn Error>> {
+  let keypair = load_identity(config, passphrase)?;
+  let mut server = ServerNode::new(keypair, config)?;
+  let listener = TcpListener::bind(&config.server.health_addr).await?;
+  println!(
+    "🩺 Health endpoint on http://{}{}",
+    listener.local_addr()?,
+    health::HEALTH_PATH
+  );
+  println!(
+    "📈 Metrics on http://{}{}",
+    listener.local_addr()?,
+    METRICS_PATH
+  );
+  tokio::spawn(health::serve(listener, server.health(), server.metrics()));
+  if config.server.relay {
+    println!("📡 Relaying connections for peers behind a NAT");
+  }
+  let silent = config.ui.silent;
+
+  let shutdown = shutdown_signal();
+  tokio::pin!(shutdown);
+  loop {
+    select! {
+      signal = &mut shutdown => {
+        signal?;
+        println!("🛑 Shutting down {}", server.local_peer_id());
+        server.shutdown().await;
+        return Ok(());
+      }
+      event = server.next_event() => match event {
+        ServerEvent::ListenAddr(addr) => {
+          println!("✅ Local node is listening on {addr}");
         }
-        SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+        ServerEvent::PeerConnected(peer_id) => {
           println!("🔗 Connected to {peer_id}");
         }
-        SwarmEvent::ConnectionClosed { peer_id, .. } => {
+        ServerEvent::PeerDisconnected(peer_id) => {
           println!("💔 Disconnected to {peer_id}");
         }
-        // Identify
-        SwarmEvent::Behaviour(MyBehaviourEvent::Identify(identify::Event::Received {
-          peer_id,
-          info,
-        })) => {
+        ServerEvent::PeerIdentified(peer_id) => {
           println!("👤 Identify new peer: {peer_id}");
-          if info.protocols.iter().any(|p| *p == kad::PROTOCOL_NAME) {
-            for addr in info.listen_addrs {
-              swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
-            }
-          }
         }
-        // Kademlia
-        SwarmEvent::Behaviour(MyBehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed {
-          result: kad::QueryResult::Bootstrap(Ok(BootstrapOk { peer: peer_id, .. })),
-          ..
-        })) => {
-          if peer_id != keypair.public().to_peer_id() {
-            println!("🚀 Kademlia bootstrapped completely: {peer_id:?}");
+        ServerEvent::Other(event) => {
+          if !silent {
+            println!("❓ Other Behaviour events {event:?}");
           }
         }
-        SwarmEvent::Behaviour(MyBehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed {
-          result: kad::QueryResult::GetClosestPeers(Ok(GetClosestPeersOk { peers, .. })),
-          ..
-        })) => {
-          println!("🔍 Kademlia discovered new peers: {peers:?}");
-        }
-        // Gossipsub
-        SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Message {
-          propagation_source: peer_id,
-          message,
-          ..
-        })) => {
-          let msg = String::from_utf8_lossy(&message.data);
-          println!("💌 Message from {peer_id}: {msg}");
+      }
+    }
+  }
+}
+
+/// Resolves on SIGTERM, as sent by `fly deploy`, or on Ctrl-C.
+#[cfg(unix)]
+async fn shutdown_signal() -> io::Result<()> {
+  let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+  select! {
+    _ = sigterm.recv() => Ok(()),
+    result = tokio::signal::ctrl_c() => result,
+  }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() -> io::Result<()> {
+  tokio::signal::ctrl_c().await
+}
+
+/// Subcommands.
+async fn run_action(
+  action: Action,
+  config: &Config,
+  passphrase: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+  let path = config.identity.keystore.as_path();
+  match action {
+    Action::Keygen { force } => {
+      let keypair = libp2p::identity::Keypair::generate_ed25519();
+      keystore::save(path, &keypair, passphrase, force)?;
+      println!(
+        "🔑 {} saved in {}",
+        keypair.public().to_peer_id(),
+        path.display()
+      );
+    }
+    Action::Export { out } => {
+      let keypair = keystore::load(path, passphrase)?;
+      keystore::export(&out, &keypair)?;
+      println!(
+        "📤 {} exported to {}",
+        keypair.public().to_peer_id(),
+        out.display()
+      );
+    }
+    Action::Import {
+      file,
+      from_seed,
+      force,
+    } => {
+      let keypair = match file {
+        Some(file) => keystore::read_exported(&file)?,
+        None if from_seed => {
+          let mut stdin = io::BufReader::new(io::stdin()).lines();
+          let seed = stdin.next_line().await?.ok_or("No seed on stdin.")?;
+          ed25519_from_seed(seed.trim_end())?
         }
-        // Others
-        _ => {
-          if silent != true {
-            println!("❓ Other Behaviour events {event:?}");
+        None => return Err("Nothing to import.".into()),
+      };
+      keystore::save(path, &keypair, passphrase, force)?;
+      println!(
+        "📥 {} imported in {}",
+        keypair.public().to_peer_id(),
+        path.display()
+      );
+    }
+    Action::Serve { .. } => serve(config, passphrase).await?,
+    Action::Config(ConfigAction::Print) => print!("{}", config.to_toml()),
+  }
+  Ok(())
+}
+
+/// Messages replayed by `/history` without a count.
+const DEFAULT_HISTORY: usize = 20;
+
+/// Verified nickname of the author if known, the one claimed in the envelope otherwise.
+fn display_name(node: &ChatNode, peer_id: &PeerId, envelope: &Envelope) -> String {
+  match node.nickname_of(peer_id).or(envelope.sender.as_deref()) {
+    Some(nick) => format!("{nick} ({peer_id})"),
+    None => peer_id.to_string(),
+  }
+}
+
+/// A stored message on one line, after its reception time.
+fn format_record(node: &ChatNode, record: HistoryRecord) -> String {
+  let time = DateTime::from_timestamp_millis(record.received_at as i64)
+    .map(|time| {
+      time
+        .with_timezone(&Local)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+    })
+    .unwrap_or_default();
+  let from = match record.author.parse() {
+    Ok(author) => display_name(node, &author, &record.envelope),
+    Err(_) => record.author,
+  };
+  match record.envelope.kind {
+    Kind::Text => format!("   {time} {from}: {}", record.envelope.body),
+    _ => format!("   {time} {from}: 🔐 undecryptable"),
+  }
+}
+
+/// Run a command, and return the lines to show in reply.
+fn handle_command(node: &mut ChatNode, current: &mut Option<String>, cmd: Command) -> Vec<String> {
+  let reply = match cmd {
+    Command::Join(room) => match node.subscribe(&room) {
+      Ok(_) => {
+        let reply = if node.is_private(&room) {
+          format!("🔒 Joined private room [{room}]")
+        } else {
+          format!("🚪 Joined [{room}]")
+        };
+        *current = Some(room);
+        reply
+      }
+      Err(er) => format!("❌ Failed to join [{room}]: {er}"),
+    },
+    Command::Leave(room) => {
+      let Some(room) = room.or_else(|| current.clone()) else {
+        return vec!["❌ No room to leave".to_string()];
+      };
+      if !node.is_joined(&room) {
+        return vec![format!("❌ Not in [{room}]")];
+      }
+      match node.unsubscribe(&room) {
+        Ok(_) => {
+          if current.as_deref() == Some(room.as_str()) {
+            *current = node.rooms().next().map(str::to_string);
           }
+          format!("👋 Left [{room}]")
         }
+        Err(er) => format!("❌ Failed to leave [{room}]: {er}"),
       }
     }
-  }
+    Command::Switch(room) => {
+      if node.is_joined(&room) {
+        let reply = format!("👉 Now talking in [{room}]");
+        *current = Some(room);
+        reply
+      } else {
+        format!("❌ Not in [{room}], /join it first")
+      }
+    }
+    Command::Rooms => {
+      return node
+        .rooms()
+        .map(|room| {
+          let marker = if current.as_deref() == Some(room) {
+            "*"
+          } else {
+            " "
+          };
+          let lock = if node.is_private(room) { " 🔒" } else { "" };
+          format!("{marker} {room}{lock}")
+        })
+        .collect();
+    }
+    Command::Nick(nick) => match node.set_nickname(&nick) {
+      Ok(()) => format!("🏷️  You are now known as {nick}"),
+      Err(er) => format!("❌ Failed to change nickname: {er}"),
+    },
+    Command::Msg { peer, text } => match node.find_peer(&peer) {
+      Ok(peer_id) => match node.send_direct(peer_id, text) {
+        Ok(_) => format!("🔒 .................. Sending to {peer}"),
+        Err(er) => format!("❌ Failed to send the message to {peer}: {er}"),
+      },
+      Err(er) => format!("❌ {er}"),
+    },
+    Command::Private(room) => match node.create_private_room(&room) {
+      Ok(()) => {
+        let reply = format!("🔒 Created private room [{room}], /invite peers to it");
+        *current = Some(room);
+        reply
+      }
+      Err(er) => format!("❌ {er}"),
+    },
+    Command::Invite { peer, room } => {
+      let Some(room) = room.or_else(|| current.clone()) else {
+        return vec!["❌ No room to invite to".to_string()];
+      };
+      match node
+        .find_peer(&peer)
+        .and_then(|peer_id| node.invite(&room, peer_id))
+      {
+        Ok(_) => format!("✉️  Sending the key of [{room}] to {peer}"),
+        Err(er) => format!("❌ Failed to invite {peer}: {er}"),
+      }
+    }
+    Command::Kick { peer, room } => {
+      let Some(room) = room.or_else(|| current.clone()) else {
+        return vec!["❌ No room to kick from".to_string()];
+      };
+      match node
+        .find_peer(&peer)
+        .and_then(|peer_id| node.kick(&room, peer_id))
+      {
+        Ok(()) => format!("🚫 Removed {peer} from [{room}] and rotated its key"),
+        Err(er) => format!("❌ Failed to kick {peer}: {er}"),
+      }
+    }
+    Command::Decline { room, peer } => {
+      let owner = match peer.as_deref().map(|peer| node.find_peer(peer)).transpose() {
+        Ok(owner) => owner,
+        Err(er) => return vec![format!("❌ {er}")],
+      };
+      match node.decline_invitation(&room, owner) {
+        0 => format!("❌ No pending invitation to [{room}]"),
+        _ => format!("🗑️  Declined the invitation to [{room}]"),
+      }
+    }
+    Command::Block(peer) => match node
+      .find_peer(&peer)
+      .and_then(|peer_id| node.block(peer_id))
+    {
+      Ok(true) => format!("⛔ Blocked {peer}, its connections and messages are refused"),
+      Ok(false) => format!("⛔ {peer} is already blocked"),
+      Err(er) => format!("❌ Failed to block {peer}: {er}"),
+    },
+    Command::Unblock(peer) => match node
+      .find_peer(&peer)
+      .and_then(|peer_id| node.unblock(&peer_id))
+    {
+      Ok(true) => format!("✅ Unblocked {peer}"),
+      Ok(false) => format!("❌ {peer} is not blocked"),
+      Err(er) => format!("❌ Failed to unblock {peer}: {er}"),
+    },
+    Command::Blocked => {
+      let blocked = node
+        .blocked()
+        .map(|peer_id| match node.nickname_of(peer_id) {
+          Some(nickname) => format!("   {peer_id} ({nickname})"),
+          None => format!("   {peer_id}"),
+        })
+        .collect::<Vec<_>>();
+      if blocked.is_empty() {
+        return vec!["⛔ No peer is blocked".to_string()];
+      }
+      return std::iter::once("⛔ Blocked peers:".to_string())
+        .chain(blocked)
+        .collect();
+    }
+    Command::History(limit) => {
+      let Some(room) = current.as_deref() else {
+        return vec!["❌ Not in any room, /join one first".to_string()];
+      };
+      let records = match node.history(room, limit.unwrap_or(DEFAULT_HISTORY)) {
+        Ok(records) => records,
+        Err(er) => return vec![format!("❌ Failed to read the history: {er}")],
+      };
+      if records.is_empty() {
+        return vec![format!("📜 No message in [{room}] yet")];
+      }
+      let mut lines = vec![format!("📜 Last {} messages of [{room}]:", records.len())];
+      for record in records {
+        lines.push(format_record(node, record));
+      }
+      return lines;
+    }
+    Command::Say(msg) => {
+      let Some(room) = current.as_deref() else {
+        return vec!["❌ Not in any room, /join one first".to_string()];
+      };
+      // Publish messages
+      match node.send_text(room, msg) {
+        Ok(_) => "🛫 .................. Sent".to_string(),
+        Err(er) => format!("❌ Failed to publish the message: {er}"),
+      }
+    }
+  };
+  vec![reply]
 }
@@ -0,0 +1,92 @@
+use libp2p::metrics::{Metrics as Libp2pMetrics, Recorder, Registry};
+use prometheus_client::{
+  encoding::text,
+  metrics::{counter::Counter, family::Family},
+};
+use std::sync::Arc;
+
+pub const METRICS_PATH: &str = "/metrics";
+/// Content type of [`encode`].
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Prometheus metrics of the libp2p protocols, bandwidth included when registered by the
+/// swarm builder. Ping round-trip times are kept as a histogram.
+pub struct Metrics {
+  libp2p: Libp2pMetrics,
+  registry: Arc<Registry>,
+}
+
+impl Metrics {
+  /// Register the metrics of the protocols into a registry holding any others.
+  pub fn new(mut registry: Registry) -> Self {
+    let libp2p = Libp2pMetrics::new(&mut registry);
+    Metrics {
+      libp2p,
+      registry: Arc::new(registry),
+    }
+  }
+
+  pub fn record<E>(&self, event: &E)
+  where
+    Libp2pMetrics: Recorder<E>,
+  {
+    self.libp2p.record(event);
+  }
+
+  /// The registry, to be encoded by the HTTP endpoints.
+  pub fn registry(&self) -> Arc<Registry> {
+    self.registry.clone()
+  }
+}
+
+/// Counters of the messages of the rooms.
+#[derive(Clone, Debug)]
+pub struct ChatMetrics {
+  /// Envelopes published, presence announcements excepted.
+  pub messages_sent: Counter,
+  /// Envelopes decoded from the rooms, ours excepted.
+  pub messages_received: Counter,
+  pub publish_failures: Counter,
+  /// Messages received from rooms and rejected, by `reason`.
+  pub messages_rejected: Family<Vec<(&'static str, &'static str)>, Counter>,
+}
+
+impl ChatMetrics {
+  pub fn new(registry: &mut Registry) -> Self {
+    let registry = registry.sub_registry_with_prefix("chat");
+    let metrics = ChatMetrics {
+      messages_sent: Counter::default(),
+      messages_received: Counter::default(),
+      publish_failures: Counter::default(),
+      messages_rejected: Family::default(),
+    };
+    registry.register(
+      "messages_sent",
+      "Messages published to rooms",
+      metrics.messages_sent.clone(),
+    );
+    registry.register(
+      "messages_received",
+      "Messages received from rooms",
+      metrics.messages_received.clone(),
+    );
+    registry.register(
+      "publish_failures",
+      "Messages that could not be published",
+      metrics.publish_failures.clone(),
+    );
+    registry.register(
+      "messages_rejected",
+      "Messages rejected, by reason",
+      metrics.messages_rejected.clone(),
+    );
+    metrics
+  }
+}
+
+/// The metrics in the OpenMetrics text format, as scraped by Prometheus.
+pub fn encode(registry: &Registry) -> String {
+  let mut buf = String::new();
+  text::encode(&mut buf, registry).expect("writing to a String does not fail");
+  buf
+}
@@ -0,0 +1,582 @@
+use futures::stream::StreamExt;
+use libp2p::{
+  autonat::{self, NatStatus},
+  dcutr, gossipsub, identify,
+  identity::Keypair,
+  kad::{self, BootstrapOk, GetClosestPeersError, GetClosestPeersOk, GetRecordOk, PeerRecord},
+  mdns,
+  metrics::Registry,
+  multiaddr::Protocol,
+  relay,
+  request_response::OutboundRequestId,
+  swarm::SwarmEvent,
+  Multiaddr, PeerId, Swarm,
+};
+use std::{
+  collections::{BTreeSet, HashMap, HashSet, VecDeque},
+  error::Error,
+  sync::Arc,
+  time::{Duration, Instant},
+};
+
+use crate::metrics::{ChatMetrics, Metrics};
+use crate::utils::{
+  blocklist::Blocklist,
+  dm::DirectRequest,
+  history::{History, HistoryRecord},
+  msg::{Envelope, Kind},
+  nick::{NickRecord, PRESENCE_TOPIC},
+  swarm,
+};
+
+mod api;
+mod behaviour;
+mod block;
+mod bootstrap;
+mod builder;
+mod direct;
+mod history;
+mod local;
+mod nat;
+mod presence;
+mod private;
+mod spam;
+mod sync;
+
+pub use behaviour::{MyBehaviour, MyBehaviourEvent};
+pub use builder::ChatNodeBuilder;
+pub use direct::DirectId;
+pub use nat::ConnectionPath;
+pub use spam::Rejection;
+pub use sync::Signatures;
+
+use spam::Limit;
+
+/// Events surfaced by [`ChatNode::next_event`].
+#[derive(Debug)]
+pub enum ChatEvent {
+  /// The node is listening on a new address (including the `/p2p/<peer id>` suffix).
+  ListenAddr(Multiaddr),
+  /// A remote is dialing us.
+  IncomingConnection(Multiaddr),
+  PeerConnected {
+    peer_id: PeerId,
+    path: ConnectionPath,
+  },
+  PeerDisconnected(PeerId),
+  /// A peer has been identified and, if it speaks Kademlia, added to the DHT.
+  PeerIdentified(PeerId),
+  /// AutoNAT found out whether we are reachable from the outside.
+  NatStatusChanged(NatStatus),
+  /// A relay accepted to forward connections to us, as we are behind a NAT.
+  RelayReserved {
+    relay: PeerId,
+  },
+  /// DCUtR tried to replace a relayed connection by a direct one.
+  HolePunched {
+    peer_id: PeerId,
+    result: Result<(), String>,
+  },
+  /// Kademlia bootstrap reached a peer.
+  Bootstrapped(PeerId),
+  /// The number of reachable bootstrap peers changed.
+  BootstrapStatus {
+    reachable: usize,
+    total: usize,
+  },
+  /// No bootstrap peer is reachable, they are dialed again. The next attempt is after `delay`.
+  BootstrapRetry {
+    attempt: u32,
+    delay: Duration,
+  },
+  /// mDNS found peers on the local network.
+  LocalPeersDiscovered(Vec<PeerId>),
+  /// Kademlia closest peers lookup finished.
+  PeersDiscovered(Vec<PeerId>),
+  /// A valid gossipsub message has been received.
+  Message {
+    propagation_source: PeerId,
+    message_id: gossipsub::MessageId,
+    message: gossipsub::Message,
+    envelope: Envelope,
+  },
+  /// A peer announced a new nickname, or it was found in the DHT.
+  NicknameChanged {
+    peer_id: PeerId,
+    nickname: String,
+  },
+  /// A direct message has been received.
+  DirectMessage {
+    peer_id: PeerId,
+    envelope: Envelope,
+  },
+  /// The peer acknowledged our direct message.
+  DirectDelivered {
+    peer_id: PeerId,
+    id: DirectId,
+  },
+  /// Our direct message could not be delivered.
+  DirectFailed {
+    peer_id: PeerId,
+    id: DirectId,
+    error: String,
+  },
+  /// The owner of a private room invited us or rotated its key.
+  RoomKeyReceived {
+    room: String,
+    peer_id: PeerId,
+    epoch: u64,
+  },
+  /// Messages of a room we missed, received from one of its members. Oldest first.
+  HistorySynced {
+    room: String,
+    peer_id: PeerId,
+    records: Vec<HistoryRecord>,
+  },
+  /// A gossipsub message was rejected. Only the first message over the rate limit in a window
+  /// is reported, on the presence topic as in the rooms.
+  MessageRejected {
+    propagation_source: PeerId,
+    author: PeerId,
+    room: String,
+    reason: Rejection,
+  },
+  /// Any other swarm event, for logging.
+  Other(SwarmEvent<MyBehaviourEvent>),
+}
+
+/// A chat peer that owns the libp2p swarm.
+///
+/// The swarm only makes progress while [`ChatNode::next_event`] is being polled, so
+/// embedders should drive it from their main loop.
+pub struct ChatNode {
+  swarm: Swarm<MyBehaviour>,
+  keypair: Keypair,
+  rooms: BTreeSet<String>,
+  /// Verified nickname records, ours included.
+  nicknames: HashMap<PeerId, NickRecord>,
+  /// Pending DHT lookups of nicknames.
+  nickname_lookups: HashMap<kad::QueryId, PeerId>,
+  next_direct_id: DirectId,
+  /// Direct messages waiting for the DHT lookup of their recipient.
+  direct_outbox: HashMap<PeerId, Vec<(DirectId, DirectRequest)>>,
+  direct_lookups: HashMap<kad::QueryId, PeerId>,
+  direct_inflight: HashMap<OutboundRequestId, DirectId>,
+  private_rooms: HashMap<String, private::PrivateRoom>,
+  /// Keys of the private rooms we were invited to, until joined or declined. Oldest first, one
+  /// per room and owner.
+  invitations: Vec<(String, private::PrivateRoom)>,
+  bootstrap: bootstrap::Bootstrap,
+  relays: nat::Relays,
+  history: Option<History>,
+  syncs: sync::Syncs,
+  signatures: Signatures,
+  blocklist: Blocklist,
+  /// Only these peers are accepted when set.
+  allowlist: Option<HashSet<PeerId>>,
+  publish_limit: spam::PublishLimit,
+  /// Separate budget of the presence topic, whose records are cheap to flood but costly to check.
+  presence_limit: spam::PublishLimit,
+  metrics: Metrics,
+  chat_metrics: ChatMetrics,
+  /// Events produced in batches, returned before polling the swarm again.
+  events: VecDeque<ChatEvent>,
+}
+
+impl ChatNode {
+  pub fn builder() -> ChatNodeBuilder {
+    ChatNodeBuilder::default()
+  }
+
+  pub fn local_peer_id(&self) -> PeerId {
+    *self.swarm.local_peer_id()
+  }
+
+  pub fn swarm(&self) -> &Swarm<MyBehaviour> {
+    &self.swarm
+  }
+
+  pub fn swarm_mut(&mut self) -> &mut Swarm<MyBehaviour> {
+    &mut self.swarm
+  }
+
+  /// Prometheus metrics of the swarm and the rooms.
+  pub fn metrics(&self) -> Arc<Registry> {
+    self.metrics.registry()
+  }
+
+  /// Names of the rooms (gossipsub topics) the node is subscribed to.
+  pub fn rooms(&self) -> impl Iterator<Item = &str> {
+    self.rooms.iter().map(String::as_str)
+  }
+
+  pub fn is_joined(&self, room: &str) -> bool {
+    self.rooms.contains(room)
+  }
+
+  /// Subscribe to a gossipsub topic, as a private room if we were invited to it. Returns
+  /// `false` if already subscribed.
+  pub fn subscribe(&mut self, topic: &str) -> Result<bool, Box<dyn Error>> {
+    let ident = gossipsub::IdentTopic::new(topic);
+    let subscribed = self.swarm.behaviour_mut().gossipsub.subscribe(&ident)?;
+    if !self.rooms.contains(topic) {
+      self.accept_invitation(topic);
+    }
+    self.rooms.insert(topic.to_string());
+    if subscribed {
+      self.score_room(ident);
+      self.sync_room(topic);
+    }
+    Ok(subscribed)
+  }
+
+  /// Unsubscribe from a gossipsub topic. Returns `false` if not subscribed.
+  pub fn unsubscribe(&mut self, topic: &str) -> Result<bool, Box<dyn Error>> {
+    let ident = gossipsub::IdentTopic::new(topic);
+    let unsubscribed = self.swarm.behaviour_mut().gossipsub.unsubscribe(&ident)?;
+    if unsubscribed {
+      self.unscore_room(ident);
+    }
+    self.rooms.remove(topic);
+    self.leave_private_room(topic);
+    self.handle_sync_left(topic);
+    Ok(unsubscribed)
+  }
+
+  /// Publish an envelope to a gossipsub topic.
+  pub fn publish(
+    &mut self,
+    topic: &str,
+    envelope: &Envelope,
+  ) -> Result<gossipsub::MessageId, gossipsub::PublishError> {
+    let topic = gossipsub::IdentTopic::new(topic);
+    let result = self
+      .swarm
+      .behaviour_mut()
+      .gossipsub
+      .publish(topic, envelope.encode());
+    if result.is_ok() {
+      self.chat_metrics.messages_sent.inc();
+    } else {
+      self.chat_metrics.publish_failures.inc();
+    }
+    result
+  }
+
+  /// Publish a plain text message signed with our nickname, sealed if the room is private.
+  pub fn send_text(
+    &mut self,
+    topic: &str,
+    body: impl Into<String>,
+  ) -> Result<gossipsub::MessageId, Box<dyn Error>> {
+    let envelope = Envelope::text(self.nickname().map(str::to_string), body);
+    let sealed = self.seal_for_room(topic, envelope.clone())?;
+    let message_id = self.publish(topic, &sealed)?;
+    let signed = self.signatures.take(&message_id);
+    self.record_history(topic, &message_id, &self.local_peer_id(), &envelope, signed);
+    Ok(message_id)
+  }
+
+  /// Drive the swarm until the next event worth reporting.
+  pub async fn next_event(&mut self) -> ChatEvent {
+    loop {
+      if let Some(event) = self.events.pop_front() {
+        return event;
+      }
+      let next_attempt = self.bootstrap.next_attempt;
+      let relay_retry = self.next_relay_retry();
+      let event = tokio::select! {
+        event = self.swarm.select_next_some() => self.handle_swarm_event(event),
+        _ = tokio::time::sleep_until(next_attempt), if self.bootstrap.is_enabled() => {
+          self.handle_bootstrap_timer()
+        }
+        _ = tokio::time::sleep_until(relay_retry.unwrap_or(next_attempt)),
+          if relay_retry.is_some() => self.handle_relay_timer(),
+      };
+      if let Some(event) = event {
+        return event;
+      }
+    }
+  }
+
+  fn handle_swarm_event(&mut self, event: SwarmEvent<MyBehaviourEvent>) -> Option<ChatEvent> {
+    self.record_metrics(&event);
+    let local_peer_id = self.local_peer_id();
+    match event {
+      SwarmEvent::NewListenAddr { address, .. } => Some(ChatEvent::ListenAddr(
+        address.with(Protocol::P2p(local_peer_id)),
+      )),
+      SwarmEvent::IncomingConnection { send_back_addr, .. } => {
+        Some(ChatEvent::IncomingConnection(send_back_addr))
+      }
+      SwarmEvent::ConnectionEstablished {
+        peer_id, endpoint, ..
+      } => {
+        self.handle_bootstrap_connection(peer_id, true);
+        self.handle_relay_connection(peer_id, &endpoint);
+        Some(ChatEvent::PeerConnected {
+          peer_id,
+          path: ConnectionPath::of(&endpoint),
+        })
+      }
+      SwarmEvent::ConnectionClosed {
+        peer_id,
+        num_established,
+        ..
+      } => {
+        if num_established == 0 {
+          self.handle_bootstrap_connection(peer_id, false);
+          self.handle_relay_disconnection(&peer_id);
+          self.handle_sync_disconnection(&peer_id);
+        }
+        Some(ChatEvent::PeerDisconnected(peer_id))
+      }
+      // Identify
+      SwarmEvent::Behaviour(MyBehaviourEvent::Identify(identify::Event::Received {
+        peer_id,
+        info,
+      })) => {
+        swarm::add_identified(&mut self.swarm.behaviour_mut().kademlia, peer_id, &info);
+        self.handle_relay_candidate(peer_id, &info.protocols);
+        Some(ChatEvent::PeerIdentified(peer_id))
+      }
+      SwarmEvent::ListenerClosed { listener_id, .. } => {
+        self.handle_relay_listener_closed(listener_id);
+        Some(ChatEvent::Other(event))
+      }
+      // AutoNAT, relay and hole punching
+      SwarmEvent::Behaviour(MyBehaviourEvent::Autonat(autonat::Event::StatusChanged {
+        new,
+        ..
+      })) => Some(self.handle_nat_status(new)),
+      SwarmEvent::Behaviour(MyBehaviourEvent::RelayClient(
+        relay::client::Event::ReservationReqAccepted {
+          relay_peer_id,
+          renewal: false,
+          ..
+        },
+      )) => {
+        self.handle_relay_reserved(&relay_peer_id);
+        Some(ChatEvent::RelayReserved {
+          relay: relay_peer_id,
+        })
+      }
+      SwarmEvent::Behaviour(MyBehaviourEvent::Dcutr(dcutr::Event {
+        remote_peer_id,
+        result,
+      })) => Some(ChatEvent::HolePunched {
+        peer_id: remote_peer_id,
+        result: result.map(|_| ()).map_err(|er| er.to_string()),
+      }),
+      // mDNS
+      SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
+        self.handle_local_peers_discovered(peers)
+      }
+      SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
+        self.handle_local_peers_expired(peers);
+        None
+      }
+      // Kademlia
+      SwarmEvent::Behaviour(MyBehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed {
+        result: kad::QueryResult::Bootstrap(Ok(BootstrapOk { peer: peer_id, .. })),
+        ..
+      })) => (peer_id != local_peer_id).then_some(ChatEvent::Bootstrapped(peer_id)),
+      SwarmEvent::Behaviour(MyBehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed {
+        id,
+        result: kad::QueryResult::GetRecord(result),
+        step,
+        ..
+      }))
+        if self.nickname_lookups.contains_key(&id) =>
+      {
+        if step.last {
+          self.nickname_lookups.remove(&id);
+        }
+        match result {
+          Ok(GetRecordOk::FoundRecord(PeerRecord { record, .. })) => {
+            self.handle_nickname_record(record)
+          }
+          _ => None,
+        }
+      }
+      SwarmEvent::Behaviour(MyBehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed {
+        id,
+        result: kad::QueryResult::GetClosestPeers(result),
+        ..
+      }))
+        if self.direct_lookups.contains_key(&id) =>
+      {
+        let peer_id = self.direct_lookups.remove(&id)?;
+        let peers = match result {
+          Ok(GetClosestPeersOk { peers, .. }) => peers,
+          Err(GetClosestPeersError::Timeout { peers, .. }) => peers,
+        };
+        self.handle_direct_lookup(peer_id, peers);
+        None
+      }
+      SwarmEvent::Behaviour(MyBehaviourEvent::Kademlia(kad::Event::OutboundQueryProgressed {
+        result: kad::QueryResult::GetClosestPeers(Ok(GetClosestPeersOk { peers, .. })),
+        ..
+      })) => Some(ChatEvent::PeersDiscovered(peers)),
+      // Gossipsub
+      SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Subscribed {
+        ref topic,
+        ..
+      }))
+        if *topic == gossipsub::IdentTopic::new(PRESENCE_TOPIC).hash() =>
+      {
+        self.announce_nickname();
+        Some(ChatEvent::Other(event))
+      }
+      SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Subscribed {
+        peer_id,
+        ref topic,
+      })) => {
+        self.request_sync(peer_id, topic.as_str());
+        Some(ChatEvent::Other(event))
+      }
+      SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+        propagation_source,
+        message_id,
+        message,
+      })) => self.handle_gossipsub_message(propagation_source, message_id, message),
+      // Direct messages
+      SwarmEvent::Behaviour(MyBehaviourEvent::Direct(event)) => self.handle_direct_event(event),
+      // History sync
+      SwarmEvent::Behaviour(MyBehaviourEvent::Sync(event)) => self.handle_sync_event(event),
+      // Others
+      event => Some(ChatEvent::Other(event)),
+    }
+  }
+
+  fn record_metrics(&self, event: &SwarmEvent<MyBehaviourEvent>) {
+    self.metrics.record(event);
+    let SwarmEvent::Behaviour(event) = event else {
+      return;
+    };
+    match event {
+      MyBehaviourEvent::Ping(event) => self.metrics.record(event),
+      MyBehaviourEvent::Identify(event) => self.metrics.record(event),
+      MyBehaviourEvent::Kademlia(event) => self.metrics.record(event),
+      MyBehaviourEvent::Relay(event) => self.metrics.record(event),
+      MyBehaviourEvent::Dcutr(event) => self.metrics.record(event),
+      MyBehaviourEvent::Gossipsub(event) => self.metrics.record(event),
+      _ => {}
+    }
+  }
+
+  fn handle_gossipsub_message(
+    &mut self,
+    propagation_source: PeerId,
+    message_id: gossipsub::MessageId,
+    message: gossipsub::Message,
+  ) -> Option<ChatEvent> {
+    let author = message.source.unwrap_or(propagation_source);
+    let topic = message.topic.clone();
+    if !self.is_allowed(&author) {
+      // Their messages are dropped, but not held against the peers relaying them
+      self.report_validation(
+        &message_id,
+        &propagation_source,
+        gossipsub::MessageAcceptance::Ignore,
+      );
+      self
+        .chat_metrics
+        .messages_rejected
+        .get_or_create(&vec![("reason", "blocked")])
+        .inc();
+      return None;
+    }
+    let presence = topic == gossipsub::IdentTopic::new(PRESENCE_TOPIC).hash();
+    let limit = match presence {
+      true => &mut self.presence_limit,
+      false => &mut self.publish_limit,
+    }
+    .check(author, Instant::now());
+    let malformed = |er: Box<dyn Error>| Rejection::Malformed(er.to_string());
+    let result = match limit {
+      Limit::Reached | Limit::Over => Err(Rejection::RateLimited),
+      Limit::Within if presence => self.handle_presence_message(&message).map_err(malformed),
+      Limit::Within => self
+        .handle_chat_message(propagation_source, message_id.clone(), message)
+        .map_err(malformed),
+    };
+    // Messages are only forwarded once they are accepted here
+    let acceptance = match &result {
+      Ok(_) => gossipsub::MessageAcceptance::Accept,
+      // Peers relaying a flood are not to blame for it
+      Err(Rejection::RateLimited) if propagation_source != author => {
+        gossipsub::MessageAcceptance::Ignore
+      }
+      Err(_) => gossipsub::MessageAcceptance::Reject,
+    };
+    self.report_validation(&message_id, &propagation_source, acceptance);
+    match result {
+      Ok(event) => {
+        if let Some(ChatEvent::Message { .. }) = &event {
+          self.chat_metrics.messages_received.inc();
+        }
+        event
+      }
+      Err(rejection) => {
+        let event = self.handle_rejection(propagation_source, author, topic, rejection);
+        // Only the first message over the limit is reported, not to flood the interfaces in turn
+        (limit != Limit::Over).then_some(event)
+      }
+    }
+  }
+
+  fn report_validation(
+    &mut self,
+    message_id: &gossipsub::MessageId,
+    propagation_source: &PeerId,
+    acceptance: gossipsub::MessageAcceptance,
+  ) {
+    if let Err(er) = self
+      .swarm
+      .behaviour_mut()
+      .gossipsub
+      .report_message_validation_result(message_id, propagation_source, acceptance)
+    {
+      tracing::warn!("Failed to report validation of {message_id}: {er}");
+    }
+  }
+
+  fn handle_chat_message(
+    &mut self,
+    propagation_source: PeerId,
+    message_id: gossipsub::MessageId,
+    message: gossipsub::Message,
+  ) -> Result<Option<ChatEvent>, Box<dyn Error>> {
+    let mut envelope = Envelope::decode(&message.data)?;
+    let room = message.topic.as_str();
+    if self.is_private(room) && envelope.kind != Kind::Encrypted {
+      return Err(
+        format!(
+          "Unsealed {:?} message in private room {room}.",
+          envelope.kind
+        )
+        .into(),
+      );
+    }
+    if envelope.kind == Kind::Encrypted {
+      // Left sealed, and shown as such, when we do not have the key
+      if let Some(opened) = self.open_for_room(room, &envelope) {
+        envelope = opened;
+      }
+    }
+    if let Some(source) = message.source {
+      self.resolve_nickname(source);
+    }
+    let author = message.source.unwrap_or(propagation_source);
+    let signed = self.signatures.take(&message_id);
+    let room = message.topic.as_str();
+    self.record_history(room, &message_id, &author, &envelope, signed);
+    Ok(Some(ChatEvent::Message {
+      propagation_source,
+      message_id,
+      message,
+      envelope,
+    }))
+  }
+}
@@ -0,0 +1,189 @@
+use libp2p::multiaddr::Protocol;
+use std::error::Error;
+
+use super::{ChatEvent, ChatNode, ConnectionPath};
+use crate::utils::{
+  api::{ApiCommand, ApiEvent, ApiRequest, ApiResult, NodeInfo, PeerInfo, RoomInfo},
+  history::HistoryRecord,
+};
+
+/// Messages returned by the `history` command without a limit.
+const DEFAULT_HISTORY: usize = 20;
+/// Most messages returned by the `history` command.
+const MAX_HISTORY: usize = 1_000;
+
+impl ChatNode {
+  /// Run a command sent by a script or the HTTP API, and report its outcome.
+  pub fn execute(&mut self, request: ApiRequest) -> ApiResult {
+    let id = request.id.clone();
+    match self.try_execute(request.command) {
+      Ok(result) => ApiResult {
+        id,
+        ok: true,
+        ..result
+      },
+      Err(er) => ApiResult::failed(id, er),
+    }
+  }
+
+  fn try_execute(&mut self, command: ApiCommand) -> Result<ApiResult, Box<dyn Error>> {
+    let mut result = ApiResult::default();
+    match command {
+      ApiCommand::Publish { room, text } => {
+        if !self.is_joined(&room) {
+          return Err(format!("Not in {room}.").into());
+        }
+        result.message_id = Some(self.send_text(&room, text)?.to_string());
+      }
+      ApiCommand::Join { room } => {
+        self.subscribe(&room)?;
+      }
+      ApiCommand::Leave { room } => {
+        if !self.is_joined(&room) {
+          return Err(format!("Not in {room}.").into());
+        }
+        self.unsubscribe(&room)?;
+      }
+      ApiCommand::Dm { peer, text } => {
+        let peer_id = self.find_peer(&peer)?;
+        result.dm_id = Some(self.send_direct(peer_id, text)?);
+      }
+      ApiCommand::Nick { nickname } => self.set_nickname(&nickname)?,
+      ApiCommand::Rooms => {
+        let rooms = self.rooms().map(|room| RoomInfo {
+          name: room.to_string(),
+          private: self.is_private(room),
+        });
+        result.rooms = Some(rooms.collect());
+      }
+      ApiCommand::Peers => {
+        let peers = self.swarm.connected_peers().map(|peer_id| PeerInfo {
+          peer_id: peer_id.to_string(),
+          nickname: self.nickname_of(peer_id).map(str::to_string),
+        });
+        result.peers = Some(peers.collect());
+      }
+      ApiCommand::Info => {
+        let local_peer_id = self.local_peer_id();
+        let listen_addrs = self
+          .swarm
+          .listeners()
+          .map(|addr| addr.clone().with(Protocol::P2p(local_peer_id)).to_string());
+        result.node = Some(NodeInfo {
+          peer_id: local_peer_id.to_string(),
+          nickname: self.nickname().map(str::to_string),
+          listen_addrs: listen_addrs.collect(),
+        });
+      }
+      ApiCommand::Block { peer } => {
+        let peer_id = self.find_peer(&peer)?;
+        self.block(peer_id)?;
+      }
+      ApiCommand::Unblock { peer } => {
+        let peer_id = self.find_peer(&peer)?;
+        if !self.unblock(&peer_id)? {
+          return Err(format!("{peer} is not blocked.").into());
+        }
+      }
+      ApiCommand::Blocked => {
+        let peers = self.blocked().map(|peer_id| PeerInfo {
+          peer_id: peer_id.to_string(),
+          nickname: self.nickname_of(peer_id).map(str::to_string),
+        });
+        result.peers = Some(peers.collect());
+      }
+      ApiCommand::History { room, limit } => {
+        let limit = limit.unwrap_or(DEFAULT_HISTORY).min(MAX_HISTORY);
+        // The signed payload is only of use to the peers it is synced to
+        let records = self.history(&room, limit)?.into_iter();
+        let records = records.map(|record| HistoryRecord {
+          signed: None,
+          ..record
+        });
+        result.history = Some(records.collect());
+      }
+    }
+    Ok(result)
+  }
+
+  /// The event as told to scripts, if it is one they are told about.
+  pub fn api_event(&self, event: &ChatEvent) -> Option<ApiEvent> {
+    Some(match event {
+      ChatEvent::ListenAddr(addr) => ApiEvent::ListenAddr {
+        address: addr.to_string(),
+      },
+      ChatEvent::PeerConnected { peer_id, path } => {
+        let (address, relay) = match path {
+          ConnectionPath::Direct(addr) => (Some(addr.to_string()), None),
+          ConnectionPath::Relayed { relay } => (None, Some(relay.to_string())),
+        };
+        ApiEvent::PeerConnected {
+          peer_id: peer_id.to_string(),
+          address,
+          relay,
+        }
+      }
+      ChatEvent::PeerDisconnected(peer_id) => ApiEvent::PeerDisconnected {
+        peer_id: peer_id.to_string(),
+      },
+      ChatEvent::NicknameChanged { peer_id, nickname } => ApiEvent::NicknameChanged {
+        peer_id: peer_id.to_string(),
+        nickname: nickname.clone(),
+      },
+      ChatEvent::BootstrapStatus { reachable, total } => ApiEvent::Bootstrap {
+        reachable: *reachable,
+        total: *total,
+      },
+      ChatEvent::Bootstrapped(peer_id) => ApiEvent::Bootstrapped {
+        peer_id: peer_id.to_string(),
+      },
+      ChatEvent::Message {
+        propagation_source,
+        message_id,
+        message,
+        envelope,
+      } => {
+        let author = message.source.unwrap_or(*propagation_source);
+        ApiEvent::Message {
+          room: message.topic.to_string(),
+          message_id: message_id.to_string(),
+          author: author.to_string(),
+          nickname: self.nickname_of(&author).map(str::to_string),
+          claimed_nickname: envelope.sender.clone(),
+          timestamp: envelope.timestamp,
+          kind: envelope.kind.clone(),
+          body: envelope.body.clone(),
+        }
+      }
+      ChatEvent::MessageRejected {
+        propagation_source,
+        author,
+        room,
+        reason,
+      } => ApiEvent::MessageRejected {
+        room: room.clone(),
+        author: author.to_string(),
+        peer_id: propagation_source.to_string(),
+        reason: reason.to_string(),
+      },
+      ChatEvent::DirectMessage { peer_id, envelope } => ApiEvent::DirectMessage {
+        peer_id: peer_id.to_string(),
+        nickname: self.nickname_of(peer_id).map(str::to_string),
+        claimed_nickname: envelope.sender.clone(),
+        timestamp: envelope.timestamp,
+        kind: envelope.kind.clone(),
+        body: envelope.body.clone(),
+      },
+      ChatEvent::DirectDelivered { peer_id, id } => ApiEvent::DirectDelivered {
+        peer_id: peer_id.to_string(),
+        dm_id: *id,
+      },
+      ChatEvent::DirectFailed { peer_id, id, error } => ApiEvent::DirectFailed {
+        peer_id: peer_id.to_string(),
+        dm_id: *id,
+        error: error.clone(),
+      },
+      _ => return None,
+    })
+  }
+}
@@ -0,0 +1,34 @@
+use libp2p::{
+  allow_block_list::{self, AllowedPeers, BlockedPeers},
+  autonat, dcutr, gossipsub, identify,
+  kad::{self, store},
+  mdns, ping, relay, request_response,
+  swarm::{behaviour::toggle::Toggle, NetworkBehaviour},
+};
+
+use super::Signatures;
+use crate::utils::{
+  dm::{DirectRequest, DirectResponse},
+  sync::{SyncRequest, SyncResponse},
+};
+
+#[derive(NetworkBehaviour)]
+pub struct MyBehaviour {
+  /// Refuses the connections of the blocked peers.
+  pub blocked: allow_block_list::Behaviour<BlockedPeers>,
+  /// Only enabled in allowlist-only mode, refusing the connections of any other peer.
+  pub allowed: Toggle<allow_block_list::Behaviour<AllowedPeers>>,
+  pub ping: ping::Behaviour, // To keep the fly alive when connecting
+  pub identify: identify::Behaviour,
+  pub kademlia: kad::Behaviour<store::MemoryStore>,
+  pub autonat: autonat::Behaviour,
+  /// Only enabled when looking for peers on the local network.
+  pub mdns: Toggle<mdns::tokio::Behaviour>,
+  pub relay_client: relay::client::Behaviour,
+  /// Only enabled on nodes relaying for others.
+  pub relay: Toggle<relay::Behaviour>,
+  pub dcutr: dcutr::Behaviour,
+  pub gossipsub: gossipsub::Behaviour<Signatures>,
+  pub direct: request_response::json::Behaviour<DirectRequest, DirectResponse>,
+  pub sync: request_response::json::Behaviour<SyncRequest, SyncResponse>,
+}
@@ -0,0 +1,40 @@
+use libp2p::PeerId;
+use std::error::Error;
+
+use super::ChatNode;
+
+impl ChatNode {
+  /// Block a peer: its connections are closed and refused, and its messages dropped even when
+  /// relayed by others. Returns `false` if it already was.
+  pub fn block(&mut self, peer_id: PeerId) -> Result<bool, Box<dyn Error>> {
+    if peer_id == self.local_peer_id() {
+      return Err("Cannot block ourselves.".into());
+    }
+    let blocked = self.blocklist.insert(peer_id)?;
+    // Closes the existing connections too
+    self.swarm.behaviour_mut().blocked.block_peer(peer_id);
+    Ok(blocked)
+  }
+
+  /// Unblock a peer. Returns `false` if it was not blocked.
+  pub fn unblock(&mut self, peer_id: &PeerId) -> Result<bool, Box<dyn Error>> {
+    let unblocked = self.blocklist.remove(peer_id)?;
+    self.swarm.behaviour_mut().blocked.unblock_peer(*peer_id);
+    Ok(unblocked)
+  }
+
+  /// The blocked peers.
+  pub fn blocked(&self) -> impl Iterator<Item = &PeerId> {
+    self.blocklist.iter()
+  }
+
+  /// Whether the messages of a peer are accepted: it is not blocked and, in allowlist-only
+  /// mode, it is allowed.
+  pub fn is_allowed(&self, peer_id: &PeerId) -> bool {
+    !self.blocklist.contains(peer_id)
+      && self
+        .allowlist
+        .as_ref()
+        .is_none_or(|allowed| allowed.contains(peer_id))
+  }
+}
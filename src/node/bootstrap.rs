@@ -0,0 +1,128 @@
+use libp2p::{Multiaddr, PeerId};
+use std::{
+  collections::{HashMap, HashSet},
+  time::Duration,
+};
+use tokio::time::Instant;
+
+use super::{ChatEvent, ChatNode};
+use crate::utils::peer::dial_quic_first;
+
+/// Delay before the first retry, doubled after each failed attempt.
+const MIN_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Bootstrap peers and the schedule of the next attempt.
+pub(super) struct Bootstrap {
+  peers: Vec<(PeerId, Multiaddr)>,
+  reachable: HashSet<PeerId>,
+  /// Consecutive attempts without any reachable bootstrap peer.
+  failures: u32,
+  /// Delay between two bootstraps once connected, to refresh the routing table.
+  interval: Duration,
+  max_backoff: Duration,
+  pub(super) next_attempt: Instant,
+}
+
+impl Bootstrap {
+  pub(super) fn new(
+    peers: Vec<(PeerId, Multiaddr)>,
+    interval: Duration,
+    max_backoff: Duration,
+  ) -> Self {
+    Bootstrap {
+      peers,
+      reachable: HashSet::new(),
+      failures: 0,
+      interval,
+      max_backoff,
+      next_attempt: Instant::now() + MIN_BACKOFF,
+    }
+  }
+
+  pub(super) fn is_enabled(&self) -> bool {
+    !self.peers.is_empty()
+  }
+
+  fn backoff(&self) -> Duration {
+    MIN_BACKOFF
+      .saturating_mul(2u32.saturating_pow(self.failures))
+      .min(self.max_backoff)
+  }
+}
+
+impl ChatNode {
+  /// Number of reachable bootstrap peers, and of configured ones.
+  pub fn bootstrap_status(&self) -> (usize, usize) {
+    (self.bootstrap.reachable.len(), self.bootstrap.peers.len())
+  }
+
+  /// Dial the unreachable bootstrap peers and refresh the routing table.
+  pub(super) fn run_bootstrap(&mut self) {
+    let mut unreachable: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
+    for (peer_id, addr) in &self.bootstrap.peers {
+      if !self.bootstrap.reachable.contains(peer_id) {
+        unreachable.entry(*peer_id).or_default().push(addr.clone());
+      }
+    }
+    for (peer_id, addrs) in unreachable {
+      // Kademlia forgets the addresses it failed to dial
+      for addr in &addrs {
+        self
+          .swarm
+          .behaviour_mut()
+          .kademlia
+          .add_address(&peer_id, addr.clone());
+      }
+      if let Err(er) = self.swarm.dial(dial_quic_first(peer_id, addrs)) {
+        tracing::warn!("Failed to dial bootstrap peer {peer_id}: {er}");
+      }
+    }
+    if let Err(er) = self.swarm.behaviour_mut().kademlia.bootstrap() {
+      tracing::warn!("Failed to run Kademlia bootstrap: {er}");
+    }
+  }
+
+  /// Retry with backoff while no bootstrap peer is reachable, re-bootstrap periodically otherwise.
+  pub(super) fn handle_bootstrap_timer(&mut self) -> Option<ChatEvent> {
+    let event = if self.bootstrap.reachable.is_empty() {
+      let delay = self.bootstrap.backoff();
+      self.bootstrap.failures += 1;
+      self.bootstrap.next_attempt = Instant::now() + delay;
+      Some(ChatEvent::BootstrapRetry {
+        attempt: self.bootstrap.failures,
+        delay,
+      })
+    } else {
+      self.bootstrap.failures = 0;
+      self.bootstrap.next_attempt = Instant::now() + self.bootstrap.interval;
+      None
+    };
+    self.run_bootstrap();
+    event
+  }
+
+  /// Track the connections to the bootstrap peers.
+  pub(super) fn handle_bootstrap_connection(&mut self, peer_id: PeerId, connected: bool) {
+    if !self.bootstrap.peers.iter().any(|(id, _)| *id == peer_id) {
+      return;
+    }
+    let changed = if connected {
+      self.bootstrap.failures = 0;
+      self.bootstrap.next_attempt = Instant::now() + self.bootstrap.interval;
+      self.bootstrap.reachable.insert(peer_id)
+    } else {
+      let removed = self.bootstrap.reachable.remove(&peer_id);
+      if self.bootstrap.reachable.is_empty() {
+        // Lost them all, reconnect soon
+        self.bootstrap.next_attempt = Instant::now() + MIN_BACKOFF;
+      }
+      removed
+    };
+    if changed {
+      let (reachable, total) = self.bootstrap_status();
+      self
+        .events
+        .push_back(ChatEvent::BootstrapStatus { reachable, total });
+    }
+  }
+}
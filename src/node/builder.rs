@@ -0,0 +1,449 @@
+use libp2p::{
+  allow_block_list, dcutr, gossipsub,
+  identity::Keypair,
+  mdns,
+  metrics::Registry,
+  ping,
+  request_response::{self, ProtocolSupport},
+  Multiaddr, PeerId,
+};
+use std::{collections::HashSet, error::Error, time::Duration};
+
+use super::{
+  bootstrap::Bootstrap,
+  spam::{PublishLimit, PRESENCE_MESSAGES, PRESENCE_WINDOW},
+  ChatNode, MyBehaviour, Signatures,
+};
+use crate::config::Config;
+use crate::metrics::{ChatMetrics, Metrics};
+use crate::utils::{
+  blocklist::Blocklist, dm::DM_PROTOCOL, history::History, nick::PRESENCE_TOPIC,
+  peer::parse_peer_id, swarm, sync::SYNC_PROTOCOL, ws::WsListener,
+};
+
+/// Configures and starts a [`ChatNode`].
+pub struct ChatNodeBuilder {
+  keypair: Option<Keypair>,
+  bootstrap: Vec<Multiaddr>,
+  port: u16,
+  quic: bool,
+  external_addrs: Vec<Multiaddr>,
+  relay_server: bool,
+  mdns: bool,
+  websocket: Option<WsListener>,
+  history: Option<History>,
+  blocklist: Blocklist,
+  allowlist: Option<HashSet<PeerId>>,
+  topics: Vec<String>,
+  nickname: Option<String>,
+  heartbeat_interval: Duration,
+  score_thresholds: gossipsub::PeerScoreThresholds,
+  rate_limit_messages: u32,
+  rate_limit_window: Duration,
+  query_timeout: Duration,
+  bootstrap_interval: Duration,
+  bootstrap_max_backoff: Duration,
+  idle_timeout: Duration,
+  identify_protocol: String,
+}
+
+impl Default for ChatNodeBuilder {
+  fn default() -> Self {
+    let config = Config::default();
+    ChatNodeBuilder {
+      keypair: None,
+      bootstrap: Vec::new(),
+      port: config.network.port,
+      quic: config.network.quic,
+      external_addrs: Vec::new(),
+      relay_server: config.server.relay,
+      mdns: config.network.mdns,
+      websocket: None,
+      history: None,
+      blocklist: Blocklist::default(),
+      allowlist: None,
+      topics: Vec::new(),
+      nickname: None,
+      heartbeat_interval: Duration::from_secs(config.gossipsub.heartbeat_interval_secs),
+      score_thresholds: gossipsub::PeerScoreThresholds {
+        gossip_threshold: config.gossipsub.gossip_threshold,
+        publish_threshold: config.gossipsub.publish_threshold,
+        graylist_threshold: config.gossipsub.graylist_threshold,
+        ..Default::default()
+      },
+      rate_limit_messages: config.gossipsub.rate_limit_messages,
+      rate_limit_window: Duration::from_secs(config.gossipsub.rate_limit_window_secs),
+      query_timeout: Duration::from_secs(config.kademlia.query_timeout_secs),
+      bootstrap_interval: Duration::from_secs(config.kademlia.bootstrap_interval_secs),
+      bootstrap_max_backoff: Duration::from_secs(config.kademlia.bootstrap_max_backoff_secs),
+      idle_timeout: Duration::from_secs(config.network.idle_timeout_secs),
+      identify_protocol: config.network.identify_protocol,
+    }
+  }
+}
+
+impl ChatNodeBuilder {
+  /// Apply a configuration, the keypair excepted.
+  pub fn from_config(config: &Config) -> Result<Self, Box<dyn Error>> {
+    let mut builder = ChatNodeBuilder::default()
+      .port(config.network.port)
+      .quic(config.network.quic)
+      .relay_server(config.server.relay)
+      .mdns(config.network.mdns)
+      .topic(&config.gossipsub.room)
+      .heartbeat_interval(Duration::from_secs(
+        config.gossipsub.heartbeat_interval_secs,
+      ))
+      .score_thresholds(
+        config.gossipsub.gossip_threshold,
+        config.gossipsub.publish_threshold,
+        config.gossipsub.graylist_threshold,
+      )
+      .rate_limit(
+        config.gossipsub.rate_limit_messages,
+        Duration::from_secs(config.gossipsub.rate_limit_window_secs),
+      )
+      .query_timeout(Duration::from_secs(config.kademlia.query_timeout_secs))
+      .bootstrap_interval(Duration::from_secs(config.kademlia.bootstrap_interval_secs))
+      .bootstrap_max_backoff(Duration::from_secs(
+        config.kademlia.bootstrap_max_backoff_secs,
+      ))
+      .idle_timeout(Duration::from_secs(config.network.idle_timeout_secs))
+      .identify_protocol(&config.network.identify_protocol);
+    for addr in &config.network.bootstrap {
+      builder = builder.bootstrap(addr.parse()?);
+    }
+    if let Some(listener) = config.network.ws_listener()? {
+      builder = builder.websocket(listener);
+    }
+    for addr in &config.network.external_addrs {
+      builder = builder.external_addr(addr.parse()?);
+    }
+    if config.history.enabled {
+      builder = builder.history(History::open(
+        &config.history_path(),
+        config.history.retention(),
+      )?);
+    }
+    builder = builder.blocklist(Blocklist::open(&config.blocklist_path())?);
+    if config.peers.allowlist_only {
+      let mut allowed = config
+        .peers
+        .allowed
+        .iter()
+        .map(|peer_id| peer_id.parse())
+        .collect::<Result<Vec<PeerId>, _>>()
+        .map_err(|er| format!("Invalid peer in peers.allowed: {er}"))?;
+      // Nothing could be reached without them
+      for addr in &config.network.bootstrap {
+        allowed.push(parse_peer_id(addr)?);
+      }
+      builder = builder.allowlist(allowed);
+    }
+    if let Some(nickname) = &config.identity.nickname {
+      builder = builder.nickname(nickname);
+    }
+    Ok(builder)
+  }
+
+  /// The node identity. A random ed25519 keypair is generated if not provided.
+  pub fn keypair(mut self, keypair: Keypair) -> Self {
+    self.keypair = Some(keypair);
+    self
+  }
+
+  /// Add a bootstrap node. The address must end with `/p2p/<peer id>`.
+  pub fn bootstrap(mut self, addr: Multiaddr) -> Self {
+    self.bootstrap.push(addr);
+    self
+  }
+
+  /// Port to listen on. `0` lets the OS assign one.
+  pub fn port(mut self, port: u16) -> Self {
+    self.port = port;
+    self
+  }
+
+  /// Listen on QUIC as well as TCP. Bootstrap nodes are dialed over QUIC first when one of
+  /// their addresses is.
+  pub fn quic(mut self, quic: bool) -> Self {
+    self.quic = quic;
+    self
+  }
+
+  /// Public address of the node, when it cannot be discovered (behind a proxy or port mapping).
+  pub fn external_addr(mut self, addr: Multiaddr) -> Self {
+    self.external_addrs.push(addr);
+    self
+  }
+
+  /// Relay connections for peers behind a NAT. Relays need a public address.
+  pub fn relay_server(mut self, relay_server: bool) -> Self {
+    self.relay_server = relay_server;
+    self
+  }
+
+  /// Discover peers on the local network with mDNS, to chat without a bootstrap node.
+  pub fn mdns(mut self, mdns: bool) -> Self {
+    self.mdns = mdns;
+    self
+  }
+
+  /// Also listen on WebSocket, for browser clients.
+  pub fn websocket(mut self, listener: WsListener) -> Self {
+    self.websocket = Some(listener);
+    self
+  }
+
+  /// Store the messages sent and received in the rooms.
+  pub fn history(mut self, history: History) -> Self {
+    self.history = Some(history);
+    self
+  }
+
+  /// Peers whose connections are refused and messages dropped, even when relayed by others.
+  pub fn blocklist(mut self, blocklist: Blocklist) -> Self {
+    self.blocklist = blocklist;
+    self
+  }
+
+  /// Only accept connections and messages from these peers, for closed deployments.
+  pub fn allowlist(mut self, peers: impl IntoIterator<Item = PeerId>) -> Self {
+    self
+      .allowlist
+      .get_or_insert_with(HashSet::new)
+      .extend(peers);
+    self
+  }
+
+  /// Gossipsub topic to subscribe to once the node is started.
+  pub fn topic(mut self, topic: impl Into<String>) -> Self {
+    self.topics.push(topic.into());
+    self
+  }
+
+  /// Nickname attached to the messages we send.
+  pub fn nickname(mut self, nickname: impl Into<String>) -> Self {
+    self.nickname = Some(nickname.into());
+    self
+  }
+
+  pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+    self.heartbeat_interval = interval;
+    self
+  }
+
+  /// Gossipsub scores below which peers are not gossiped with, not sent our messages, and
+  /// ignored altogether.
+  pub fn score_thresholds(mut self, gossip: f64, publish: f64, graylist: f64) -> Self {
+    self.score_thresholds.gossip_threshold = gossip;
+    self.score_thresholds.publish_threshold = publish;
+    self.score_thresholds.graylist_threshold = graylist;
+    self
+  }
+
+  /// Room messages accepted from a peer in each window, no limit if `messages` is 0.
+  pub fn rate_limit(mut self, messages: u32, window: Duration) -> Self {
+    self.rate_limit_messages = messages;
+    self.rate_limit_window = window;
+    self
+  }
+
+  pub fn query_timeout(mut self, timeout: Duration) -> Self {
+    self.query_timeout = timeout;
+    self
+  }
+
+  /// Delay between two Kademlia bootstraps once a bootstrap peer is reachable.
+  pub fn bootstrap_interval(mut self, interval: Duration) -> Self {
+    self.bootstrap_interval = interval;
+    self
+  }
+
+  /// Upper bound of the delay between two attempts to reach the bootstrap peers.
+  pub fn bootstrap_max_backoff(mut self, max_backoff: Duration) -> Self {
+    self.bootstrap_max_backoff = max_backoff;
+    self
+  }
+
+  /// Connections are closed after being idle for this long.
+  pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+    self.idle_timeout = timeout;
+    self
+  }
+
+  /// Protocol version announced by identify.
+  pub fn identify_protocol(mut self, protocol: impl Into<String>) -> Self {
+    self.identify_protocol = protocol.into();
+    self
+  }
+
+  /// Build the swarm, start listening, bootstrap and subscribe to the topics.
+  pub fn build(self) -> Result<ChatNode, Box<dyn Error>> {
+    let Self {
+      keypair,
+      bootstrap,
+      port,
+      quic,
+      external_addrs,
+      relay_server,
+      mdns,
+      websocket,
+      history,
+      blocklist,
+      allowlist,
+      topics,
+      nickname,
+      heartbeat_interval,
+      score_thresholds,
+      rate_limit_messages,
+      rate_limit_window,
+      query_timeout,
+      bootstrap_interval,
+      bootstrap_max_backoff,
+      idle_timeout,
+      identify_protocol,
+    } = self;
+    let keypair = keypair.unwrap_or_else(Keypair::generate_ed25519);
+    let signatures = Signatures::new(keypair.clone());
+
+    let mut registry = Registry::default();
+    let mut swarm = swarm::build(
+      keypair.clone(),
+      websocket.as_ref(),
+      &mut registry,
+      idle_timeout,
+      |key, relay_client| {
+        // Create the allow and block lists.
+        let mut blocked = allow_block_list::Behaviour::default();
+        for peer_id in blocklist.iter() {
+          blocked.block_peer(*peer_id);
+        }
+        let allowed = allowlist.as_ref().map(|peers| {
+          let mut allowed = allow_block_list::Behaviour::default();
+          for peer_id in peers {
+            allowed.allow_peer(*peer_id);
+          }
+          allowed
+        });
+        // Create a Ping behaviour
+        let ping = ping::Behaviour::default();
+        // Create the Identify, Kademlia and AutoNAT behaviours, as servers do.
+        let identify = swarm::identify(key, identify_protocol);
+        let kademlia = swarm::kademlia(key, query_timeout);
+        let autonat = swarm::autonat(key);
+        // Create a mDNS behaviour.
+        let mdns = if mdns {
+          let peer_id = key.public().to_peer_id();
+          Some(mdns::tokio::Behaviour::new(
+            mdns::Config::default(),
+            peer_id,
+          )?)
+        } else {
+          None
+        };
+        // Create the relay behaviours, and DCUtR to upgrade relayed connections.
+        let relay = swarm::relay_server(key, relay_server);
+        let dcutr = dcutr::Behaviour::new(key.public().to_peer_id());
+        // Create a Gossipsub behaviour, scoring the peers of each room once joined.
+        let ids = signatures.clone();
+        let mut gossipsub = gossipsub::Behaviour::new_with_transform(
+          gossipsub::MessageAuthenticity::Signed(key.clone()),
+          gossipsub::ConfigBuilder::default()
+            .heartbeat_interval(heartbeat_interval)
+            .validation_mode(gossipsub::ValidationMode::Strict)
+            .validate_messages()
+            .message_id_fn(move |message| ids.message_id(message))
+            .build()?,
+          None,
+          signatures.clone(),
+        )?;
+        // Not penalizing peers sharing an IP: behind an office NAT or on a LAN, a dozen of them
+        // would otherwise be graylisted, and the whitelist cannot hold whole ranges
+        let score_params = gossipsub::PeerScoreParams {
+          ip_colocation_factor_weight: 0.0,
+          ..Default::default()
+        };
+        gossipsub.with_peer_score(score_params, score_thresholds)?;
+        // Create a direct messages behaviour.
+        let direct = request_response::json::Behaviour::new(
+          [(DM_PROTOCOL, ProtocolSupport::Full)],
+          request_response::Config::default(),
+        );
+        // Create a history sync behaviour.
+        let sync = request_response::json::Behaviour::new(
+          [(SYNC_PROTOCOL, ProtocolSupport::Full)],
+          request_response::Config::default(),
+        );
+        // Return my behavour
+        Ok(MyBehaviour {
+          blocked,
+          allowed: allowed.into(),
+          ping,
+          identify,
+          kademlia,
+          autonat,
+          mdns: mdns.into(),
+          relay_client,
+          relay,
+          dcutr,
+          gossipsub,
+          direct,
+          sync,
+        })
+      },
+    )?;
+
+    // Peer node: Listen on all interfaces and whatever port the OS assigns
+    swarm::listen(&mut swarm, port, quic, websocket.as_ref(), external_addrs)?;
+
+    let bootstrap = bootstrap
+      .into_iter()
+      .map(|addr| Ok((parse_peer_id(&addr.to_string())?, addr)))
+      .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    let bootstrap = Bootstrap::new(bootstrap, bootstrap_interval, bootstrap_max_backoff);
+
+    let chat_metrics = ChatMetrics::new(&mut registry);
+    let mut node = ChatNode {
+      swarm,
+      keypair,
+      rooms: Default::default(),
+      nicknames: Default::default(),
+      nickname_lookups: Default::default(),
+      next_direct_id: 0,
+      direct_outbox: Default::default(),
+      direct_lookups: Default::default(),
+      direct_inflight: Default::default(),
+      private_rooms: Default::default(),
+      invitations: Default::default(),
+      bootstrap,
+      relays: Default::default(),
+      history,
+      syncs: Default::default(),
+      signatures,
+      blocklist,
+      allowlist,
+      publish_limit: PublishLimit::new(rate_limit_messages, rate_limit_window),
+      presence_limit: PublishLimit::new(PRESENCE_MESSAGES, PRESENCE_WINDOW),
+      metrics: Metrics::new(registry),
+      chat_metrics,
+      events: Default::default(),
+    };
+    let presence = gossipsub::IdentTopic::new(PRESENCE_TOPIC);
+    node.swarm.behaviour_mut().gossipsub.subscribe(&presence)?;
+    node.score_room(presence);
+    if let Some(nickname) = nickname {
+      node.set_nickname(&nickname)?;
+    }
+
+    if node.bootstrap.is_enabled() {
+      node.run_bootstrap();
+    }
+
+    for topic in topics {
+      node.subscribe(&topic)?;
+    }
+
+    Ok(node)
+  }
+}
@@ -0,0 +1,177 @@
+use libp2p::{request_response, PeerId};
+use std::error::Error;
+
+use super::{ChatEvent, ChatNode};
+use crate::utils::{
+  dm::{DirectRequest, DirectResponse},
+  msg::{Envelope, Kind},
+};
+
+/// Local handle of a direct message, reported back in its delivery events.
+pub type DirectId = u64;
+
+impl ChatNode {
+  /// Find a peer by PeerId or by one of the known nicknames.
+  pub fn find_peer(&self, peer: &str) -> Result<PeerId, Box<dyn Error>> {
+    if let Ok(peer_id) = peer.parse() {
+      return Ok(peer_id);
+    }
+    let mut matches = self
+      .nicknames
+      .iter()
+      .filter(|(_, record)| record.nickname == peer)
+      .map(|(peer_id, _)| *peer_id);
+    match (matches.next(), matches.next()) {
+      (Some(peer_id), None) => Ok(peer_id),
+      (Some(_), Some(_)) => Err(format!("Several peers are named {peer}, use a PeerId.").into()),
+      (None, _) => Err(format!("Unknown peer {peer}.").into()),
+    }
+  }
+
+  /// Send an end-to-end encrypted text to a single peer.
+  ///
+  /// The peer is looked up in the DHT first when we are not connected to it. The
+  /// outcome is reported by [`ChatEvent::DirectDelivered`] or [`ChatEvent::DirectFailed`].
+  pub fn send_direct(
+    &mut self,
+    peer_id: PeerId,
+    body: impl Into<String>,
+  ) -> Result<DirectId, Box<dyn Error>> {
+    let envelope = Envelope::text(self.nickname().map(str::to_string), body);
+    self.send_direct_envelope(peer_id, &envelope)
+  }
+
+  /// Send any envelope to a single peer, see [`ChatNode::send_direct`].
+  pub fn send_direct_envelope(
+    &mut self,
+    peer_id: PeerId,
+    envelope: &Envelope,
+  ) -> Result<DirectId, Box<dyn Error>> {
+    let request = DirectRequest::seal(&self.keypair, &peer_id, envelope)?;
+    let id = self.next_direct_id;
+    self.next_direct_id += 1;
+    if self.swarm.is_connected(&peer_id) {
+      self.send_direct_request(id, peer_id, request);
+    } else {
+      self
+        .direct_outbox
+        .entry(peer_id)
+        .or_default()
+        .push((id, request));
+      if !self.direct_lookups.values().any(|p| *p == peer_id) {
+        let query_id = self
+          .swarm
+          .behaviour_mut()
+          .kademlia
+          .get_closest_peers(peer_id);
+        self.direct_lookups.insert(query_id, peer_id);
+      }
+    }
+    Ok(id)
+  }
+
+  fn send_direct_request(&mut self, id: DirectId, peer_id: PeerId, request: DirectRequest) {
+    let request_id = self
+      .swarm
+      .behaviour_mut()
+      .direct
+      .send_request(&peer_id, request);
+    self.direct_inflight.insert(request_id, id);
+  }
+
+  /// Flush the messages waiting for the DHT lookup of `peer_id`.
+  pub(super) fn handle_direct_lookup(&mut self, peer_id: PeerId, peers: Vec<PeerId>) {
+    let pending = self.direct_outbox.remove(&peer_id).unwrap_or_default();
+    let found = peers.contains(&peer_id) || self.swarm.is_connected(&peer_id);
+    for (id, request) in pending {
+      if found {
+        self.send_direct_request(id, peer_id, request);
+      } else {
+        self.events.push_back(ChatEvent::DirectFailed {
+          peer_id,
+          id,
+          error: "Peer unreachable, not found in the DHT".to_string(),
+        });
+      }
+    }
+  }
+
+  pub(super) fn handle_direct_event(
+    &mut self,
+    event: request_response::Event<DirectRequest, DirectResponse>,
+  ) -> Option<ChatEvent> {
+    match event {
+      request_response::Event::Message {
+        peer,
+        message: request_response::Message::Request {
+          request, channel, ..
+        },
+      } => {
+        // The connection is authenticated, so `peer` is who sealed the message
+        let result = request
+          .open(&self.keypair, &peer)
+          .and_then(|envelope| match envelope.kind {
+            Kind::RoomKey => self.handle_room_key(peer, &envelope),
+            _ => {
+              self.resolve_nickname(peer);
+              Ok(Some(ChatEvent::DirectMessage {
+                peer_id: peer,
+                envelope,
+              }))
+            }
+          });
+        let (response, event) = match result {
+          Ok(event) => (DirectResponse::Delivered, event),
+          Err(er) => {
+            tracing::warn!("Rejected direct message from {peer}: {er}");
+            (DirectResponse::Rejected(er.to_string()), None)
+          }
+        };
+        if let Err(er) = self
+          .swarm
+          .behaviour_mut()
+          .direct
+          .send_response(channel, response)
+        {
+          tracing::warn!("Failed to acknowledge direct message from {peer}: {er:?}");
+        }
+        event
+      }
+      request_response::Event::Message {
+        peer,
+        message:
+          request_response::Message::Response {
+            request_id,
+            response,
+          },
+      } => {
+        let id = self.direct_inflight.remove(&request_id)?;
+        Some(match response {
+          DirectResponse::Delivered => ChatEvent::DirectDelivered { peer_id: peer, id },
+          DirectResponse::Rejected(error) => ChatEvent::DirectFailed {
+            peer_id: peer,
+            id,
+            error,
+          },
+        })
+      }
+      request_response::Event::OutboundFailure {
+        peer,
+        request_id,
+        error,
+      } => {
+        let id = self.direct_inflight.remove(&request_id)?;
+        Some(ChatEvent::DirectFailed {
+          peer_id: peer,
+          id,
+          error: error.to_string(),
+        })
+      }
+      request_response::Event::InboundFailure { peer, error, .. } => {
+        tracing::warn!("Failed to receive direct message from {peer}: {error}");
+        None
+      }
+      request_response::Event::ResponseSent { .. } => None,
+    }
+  }
+}
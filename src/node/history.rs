@@ -0,0 +1,42 @@
+use libp2p::{gossipsub, PeerId};
+use std::error::Error;
+
+use super::ChatNode;
+use crate::utils::{
+  history::HistoryRecord,
+  msg::{Envelope, SignedMessage},
+};
+
+impl ChatNode {
+  /// The last `limit` messages of a room, oldest first.
+  pub fn history(&self, room: &str, limit: usize) -> Result<Vec<HistoryRecord>, Box<dyn Error>> {
+    let history = self.history.as_ref().ok_or("History is disabled.")?;
+    history.last(room, limit)
+  }
+
+  /// Store a message sent or received in a room, if history is enabled.
+  pub(super) fn record_history(
+    &self,
+    room: &str,
+    message_id: &gossipsub::MessageId,
+    author: &PeerId,
+    envelope: &Envelope,
+    signed: Option<SignedMessage>,
+  ) {
+    let Some(history) = &self.history else {
+      return;
+    };
+    let record = HistoryRecord {
+      signed,
+      ..HistoryRecord::new(
+        room,
+        &message_id.to_string(),
+        &author.to_string(),
+        envelope.clone(),
+      )
+    };
+    if let Err(er) = history.insert(&record) {
+      tracing::warn!("Failed to store message {message_id} in the history: {er}");
+    }
+  }
+}
@@ -0,0 +1,45 @@
+use libp2p::{Multiaddr, PeerId};
+use std::collections::BTreeSet;
+
+use super::{ChatEvent, ChatNode};
+use crate::utils::peer::is_quic;
+
+impl ChatNode {
+  /// Add the peers found by mDNS to the DHT, and keep gossipsub connected to them so that a LAN
+  /// without any bootstrap node still forms a mesh.
+  pub(super) fn handle_local_peers_discovered(
+    &mut self,
+    mut peers: Vec<(PeerId, Multiaddr)>,
+  ) -> Option<ChatEvent> {
+    // Kademlia offers the addresses to dials in the order they were added
+    peers.sort_by_key(|(_, addr)| !is_quic(addr));
+    let local_peer_id = self.local_peer_id();
+    let mut discovered = BTreeSet::new();
+    for (peer_id, addr) in peers {
+      if peer_id == local_peer_id {
+        continue;
+      }
+      let behaviour = self.swarm.behaviour_mut();
+      behaviour.kademlia.add_address(&peer_id, addr);
+      behaviour.gossipsub.add_explicit_peer(&peer_id);
+      discovered.insert(peer_id);
+    }
+    (!discovered.is_empty())
+      .then(|| ChatEvent::LocalPeersDiscovered(discovered.into_iter().collect()))
+  }
+
+  /// Forget the peers mDNS has not seen for a while.
+  pub(super) fn handle_local_peers_expired(&mut self, peers: Vec<(PeerId, Multiaddr)>) {
+    for (peer_id, addr) in peers {
+      let behaviour = self.swarm.behaviour_mut();
+      behaviour.kademlia.remove_address(&peer_id, &addr);
+      let still_seen = behaviour
+        .mdns
+        .as_ref()
+        .is_some_and(|mdns| mdns.discovered_nodes().any(|p| *p == peer_id));
+      if !still_seen {
+        behaviour.gossipsub.remove_explicit_peer(&peer_id);
+      }
+    }
+  }
+}
@@ -0,0 +1,209 @@
+use libp2p::{
+  autonat::NatStatus,
+  core::{transport::ListenerId, ConnectedPoint},
+  multiaddr::Protocol,
+  relay, Multiaddr, PeerId, StreamProtocol,
+};
+use std::{collections::HashMap, fmt, time::Duration};
+use tokio::time::Instant;
+
+use super::{ChatEvent, ChatNode};
+
+/// Relays we keep a reservation on while behind a NAT.
+const MAX_RESERVATIONS: usize = 2;
+/// Delay before reserving again on a relay that closed our reservation, doubled each time.
+const MIN_RELAY_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_RELAY_BACKOFF: Duration = Duration::from_secs(300);
+
+/// How a connection reaches the remote peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionPath {
+  Direct(Multiaddr),
+  /// Through a circuit on a relay, until DCUtR upgrades it to a direct connection.
+  Relayed {
+    relay: PeerId,
+  },
+}
+
+impl ConnectionPath {
+  pub fn of(endpoint: &ConnectedPoint) -> Self {
+    let addr = endpoint.get_remote_address();
+    let mut relay = None;
+    for protocol in addr.iter() {
+      match protocol {
+        Protocol::P2p(peer_id) => relay = Some(peer_id),
+        Protocol::P2pCircuit => {
+          if let Some(relay) = relay {
+            return ConnectionPath::Relayed { relay };
+          }
+        }
+        _ => {}
+      }
+    }
+    ConnectionPath::Direct(addr.clone())
+  }
+
+  pub fn is_relayed(&self) -> bool {
+    matches!(self, ConnectionPath::Relayed { .. })
+  }
+}
+
+impl fmt::Display for ConnectionPath {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ConnectionPath::Direct(addr) => write!(f, "directly via {addr}"),
+      ConnectionPath::Relayed { relay } => write!(f, "through relay {relay}"),
+    }
+  }
+}
+
+/// Relay candidates and our reservations on them.
+#[derive(Default)]
+pub(super) struct Relays {
+  /// Addresses we dialed, the only ones known to be reachable from here.
+  dialed: HashMap<PeerId, Multiaddr>,
+  /// Circuit addresses to listen on, for the peers offering to relay.
+  candidates: HashMap<PeerId, Multiaddr>,
+  reservations: HashMap<ListenerId, PeerId>,
+  /// Relays that closed or refused our reservations, skipped until their backoff expires.
+  backoffs: HashMap<PeerId, RelayBackoff>,
+}
+
+/// Reservations closed by a relay since the last one it accepted, and when to try it again.
+struct RelayBackoff {
+  failures: u32,
+  retry_at: Instant,
+}
+
+impl ChatNode {
+  /// Relays we are reachable through.
+  pub fn relays(&self) -> impl Iterator<Item = &PeerId> {
+    self.relays.reservations.values()
+  }
+
+  pub fn nat_status(&self) -> NatStatus {
+    self.swarm.behaviour().autonat.nat_status()
+  }
+
+  /// Remember how we reached a peer, in case it turns out to be a relay.
+  pub(super) fn handle_relay_connection(&mut self, peer_id: PeerId, endpoint: &ConnectedPoint) {
+    if let ConnectedPoint::Dialer { address, .. } = endpoint {
+      if !ConnectionPath::of(endpoint).is_relayed() {
+        let mut address = address.clone();
+        if let Some(Protocol::P2p(_)) = address.iter().last() {
+          address.pop();
+        }
+        self.relays.dialed.insert(peer_id, address);
+      }
+    }
+  }
+
+  pub(super) fn handle_relay_disconnection(&mut self, peer_id: &PeerId) {
+    self.relays.dialed.remove(peer_id);
+    self.relays.candidates.remove(peer_id);
+  }
+
+  /// Called once a peer is identified, reserve a slot on it if it relays and we need one.
+  pub(super) fn handle_relay_candidate(&mut self, peer_id: PeerId, protocols: &[StreamProtocol]) {
+    if !protocols.contains(&relay::HOP_PROTOCOL_NAME) {
+      return;
+    }
+    let Some(addr) = self.relays.dialed.get(&peer_id) else {
+      return;
+    };
+    let circuit = addr
+      .clone()
+      .with(Protocol::P2p(peer_id))
+      .with(Protocol::P2pCircuit);
+    self.relays.candidates.insert(peer_id, circuit);
+    self.reserve_relays();
+  }
+
+  /// Listen on relays while AutoNAT reports that we are not reachable, stop once we are.
+  pub(super) fn handle_nat_status(&mut self, status: NatStatus) -> ChatEvent {
+    match status {
+      NatStatus::Private => self.reserve_relays(),
+      NatStatus::Public(_) => {
+        // Not held against the relays once closed
+        for (listener_id, _) in self.relays.reservations.drain() {
+          self.swarm.remove_listener(listener_id);
+        }
+      }
+      NatStatus::Unknown => {}
+    }
+    ChatEvent::NatStatusChanged(status)
+  }
+
+  pub(super) fn handle_relay_reserved(&mut self, relay: &PeerId) {
+    self.relays.backoffs.remove(relay);
+  }
+
+  /// A relay listener stopped, because the relay went away or refused the reservation. Try
+  /// another one, and that relay again after a backoff.
+  pub(super) fn handle_relay_listener_closed(&mut self, listener_id: ListenerId) {
+    let Some(peer_id) = self.relays.reservations.remove(&listener_id) else {
+      return;
+    };
+    let backoff = self.relays.backoffs.entry(peer_id).or_insert(RelayBackoff {
+      failures: 0,
+      retry_at: Instant::now(),
+    });
+    let delay = MIN_RELAY_BACKOFF
+      .saturating_mul(2u32.saturating_pow(backoff.failures))
+      .min(MAX_RELAY_BACKOFF);
+    backoff.failures += 1;
+    backoff.retry_at = Instant::now() + delay;
+    self.reserve_relays();
+  }
+
+  /// When a relay we still need is out of its backoff.
+  pub(super) fn next_relay_retry(&self) -> Option<Instant> {
+    if self.relays.reservations.len() >= MAX_RESERVATIONS || self.nat_status() != NatStatus::Private
+    {
+      return None;
+    }
+    let now = Instant::now();
+    self
+      .relays
+      .candidates
+      .keys()
+      .filter_map(|peer_id| self.relays.backoffs.get(peer_id))
+      .map(|backoff| backoff.retry_at)
+      .filter(|retry_at| *retry_at > now)
+      .min()
+  }
+
+  pub(super) fn handle_relay_timer(&mut self) -> Option<ChatEvent> {
+    self.reserve_relays();
+    None
+  }
+
+  fn reserve_relays(&mut self) {
+    if self.nat_status() != NatStatus::Private {
+      return;
+    }
+    let now = Instant::now();
+    let available: Vec<(PeerId, Multiaddr)> = self
+      .relays
+      .candidates
+      .iter()
+      .filter(|(peer_id, _)| !self.relays.reservations.values().any(|r| r == *peer_id))
+      .filter(|(peer_id, _)| {
+        let backoff = self.relays.backoffs.get(*peer_id);
+        backoff.is_none_or(|backoff| backoff.retry_at <= now)
+      })
+      .map(|(peer_id, addr)| (*peer_id, addr.clone()))
+      .collect();
+    for (peer_id, addr) in available {
+      if self.relays.reservations.len() >= MAX_RESERVATIONS {
+        break;
+      }
+      match self.swarm.listen_on(addr) {
+        Ok(listener_id) => {
+          self.relays.reservations.insert(listener_id, peer_id);
+        }
+        Err(er) => tracing::warn!("Failed to listen on relay {peer_id}: {er}"),
+      }
+    }
+  }
+}
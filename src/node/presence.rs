@@ -0,0 +1,106 @@
+use libp2p::{
+  gossipsub,
+  kad::{self, Quorum},
+  PeerId,
+};
+use std::error::Error;
+
+use super::{ChatEvent, ChatNode};
+use crate::utils::nick::{nickname_key, NickRecord, PRESENCE_TOPIC};
+
+impl ChatNode {
+  pub fn nickname(&self) -> Option<&str> {
+    self.nickname_of(&self.local_peer_id())
+  }
+
+  /// Sign a new nickname, store it in the DHT and broadcast it on the presence topic.
+  pub fn set_nickname(&mut self, nickname: &str) -> Result<(), Box<dyn Error>> {
+    let record = NickRecord::new(&self.keypair, nickname)?;
+    self.nicknames.insert(self.local_peer_id(), record);
+    self.announce_nickname();
+    Ok(())
+  }
+
+  /// Last known nickname of a peer.
+  pub fn nickname_of(&self, peer_id: &PeerId) -> Option<&str> {
+    self.nicknames.get(peer_id).map(|r| r.nickname.as_str())
+  }
+
+  /// Look the nickname of a peer up in the DHT unless it is already known.
+  pub fn resolve_nickname(&mut self, peer_id: PeerId) {
+    if self.nicknames.contains_key(&peer_id)
+      || self.nickname_lookups.values().any(|p| *p == peer_id)
+    {
+      return;
+    }
+    let query_id = self
+      .swarm
+      .behaviour_mut()
+      .kademlia
+      .get_record(nickname_key(&peer_id));
+    self.nickname_lookups.insert(query_id, peer_id);
+  }
+
+  /// Publish our nickname record, if any. Called again when new peers show up.
+  pub(super) fn announce_nickname(&mut self) {
+    let local_peer_id = self.local_peer_id();
+    let Some(data) = self.nicknames.get(&local_peer_id).map(NickRecord::encode) else {
+      return;
+    };
+    let record = kad::Record::new(nickname_key(&local_peer_id), data.clone());
+    if let Err(er) = self
+      .swarm
+      .behaviour_mut()
+      .kademlia
+      .put_record(record, Quorum::One)
+    {
+      tracing::warn!("Failed to store the nickname record: {er}");
+    }
+    let topic = gossipsub::IdentTopic::new(PRESENCE_TOPIC);
+    match self.swarm.behaviour_mut().gossipsub.publish(topic, data) {
+      // Nobody is listening yet, peers will get it when they subscribe
+      Ok(_) | Err(gossipsub::PublishError::InsufficientPeers) => {}
+      Err(er) => tracing::warn!("Failed to announce the nickname: {er}"),
+    }
+  }
+
+  /// Validate a nickname record received on the presence topic.
+  pub(super) fn handle_presence_message(
+    &mut self,
+    message: &gossipsub::Message,
+  ) -> Result<Option<ChatEvent>, Box<dyn Error>> {
+    let (peer_id, record) = NickRecord::decode(&message.data)?;
+    if message.source != Some(peer_id) {
+      return Err("Nickname record announced by another peer.".into());
+    }
+    Ok(self.update_nickname(peer_id, record))
+  }
+
+  /// Cache a nickname record found in the DHT.
+  pub(super) fn handle_nickname_record(&mut self, record: kad::Record) -> Option<ChatEvent> {
+    match NickRecord::decode(&record.value) {
+      Ok((peer_id, nick)) if record.key == nickname_key(&peer_id) => {
+        self.update_nickname(peer_id, nick)
+      }
+      Ok(_) => {
+        tracing::warn!("Nickname record stored under a foreign key");
+        None
+      }
+      Err(er) => {
+        tracing::warn!("Invalid nickname record: {er}");
+        None
+      }
+    }
+  }
+
+  fn update_nickname(&mut self, peer_id: PeerId, record: NickRecord) -> Option<ChatEvent> {
+    let previous = self.nicknames.get(&peer_id);
+    if previous.is_some_and(|cached| cached.timestamp >= record.timestamp) {
+      return None;
+    }
+    let changed = previous.is_none_or(|cached| cached.nickname != record.nickname);
+    let nickname = record.nickname.clone();
+    self.nicknames.insert(peer_id, record);
+    changed.then_some(ChatEvent::NicknameChanged { peer_id, nickname })
+  }
+}
@@ -0,0 +1,227 @@
+use libp2p::PeerId;
+use std::{collections::BTreeSet, error::Error};
+
+use super::{ChatEvent, ChatNode, DirectId};
+use crate::utils::{msg::Envelope, room::RoomKey};
+
+/// Number of keys kept per room, so that messages sealed just before a rotation still open.
+const KEPT_KEYS: usize = 2;
+/// Invitations kept until joined or declined, later ones are refused.
+const MAX_INVITATIONS: usize = 64;
+/// Invitations kept from each peer, so that one cannot fill them all.
+const MAX_PEER_INVITATIONS: usize = 4;
+
+pub(super) struct PrivateRoom {
+  owner: PeerId,
+  /// Oldest first, the last one is the current key.
+  keys: Vec<RoomKey>,
+  /// Members invited by us. Only known to the owner.
+  members: BTreeSet<PeerId>,
+}
+
+impl PrivateRoom {
+  fn current_key(&self) -> &RoomKey {
+    self.keys.last().expect("A private room always has a key")
+  }
+
+  fn push_key(&mut self, key: RoomKey) {
+    self.keys.push(key);
+    if self.keys.len() > KEPT_KEYS {
+      self.keys.remove(0);
+    }
+  }
+
+  /// Take a key sent by `peer_id`. Returns `false` if it is not newer than ours.
+  fn rotate(&mut self, peer_id: PeerId, key: RoomKey) -> Result<bool, Box<dyn Error>> {
+    if self.owner != peer_id {
+      return Err(format!("Only the owner of {} can rotate its key.", key.room).into());
+    }
+    if key.epoch <= self.current_key().epoch {
+      return Ok(false);
+    }
+    self.push_key(key);
+    Ok(true)
+  }
+}
+
+impl ChatNode {
+  pub fn is_private(&self, room: &str) -> bool {
+    self.private_rooms.contains_key(room)
+  }
+
+  /// Owners of the pending invitations to a room, oldest first. Joining the room accepts the
+  /// last one.
+  pub fn invitations(&self, room: &str) -> Vec<PeerId> {
+    self
+      .invitations
+      .iter()
+      .filter(|(name, _)| name == room)
+      .map(|(_, invitation)| invitation.owner)
+      .collect()
+  }
+
+  /// Forget the invitations to a room, only the one of `owner` if given. Returns how many were
+  /// declined.
+  pub fn decline_invitation(&mut self, room: &str, owner: Option<PeerId>) -> usize {
+    let pending = self.invitations.len();
+    self.invitations.retain(|(name, invitation)| {
+      name != room || owner.is_some_and(|owner| owner != invitation.owner)
+    });
+    pending - self.invitations.len()
+  }
+
+  /// Create a private room owned by us and join it.
+  pub fn create_private_room(&mut self, room: &str) -> Result<(), Box<dyn Error>> {
+    if self.is_joined(room) || self.is_private(room) {
+      return Err(format!("Room {room} already exists.").into());
+    }
+    self.decline_invitation(room, None);
+    let local_peer_id = self.local_peer_id();
+    let private_room = PrivateRoom {
+      owner: local_peer_id,
+      keys: vec![RoomKey::generate(room, 0)],
+      members: BTreeSet::from([local_peer_id]),
+    };
+    self.private_rooms.insert(room.to_string(), private_room);
+    self.subscribe(room)?;
+    Ok(())
+  }
+
+  /// Send the room key to a new member.
+  pub fn invite(&mut self, room: &str, peer_id: PeerId) -> Result<DirectId, Box<dyn Error>> {
+    let envelope = self.owned_room(room)?.current_key().to_envelope();
+    let id = self.send_direct_envelope(peer_id, &envelope)?;
+    if let Some(private_room) = self.private_rooms.get_mut(room) {
+      private_room.members.insert(peer_id);
+    }
+    Ok(id)
+  }
+
+  /// Remove a member and rotate the key, so that it cannot read the next messages.
+  pub fn kick(&mut self, room: &str, peer_id: PeerId) -> Result<(), Box<dyn Error>> {
+    let private_room = self.owned_room(room)?;
+    if !private_room.members.contains(&peer_id) {
+      return Err(format!("{peer_id} is not a member of {room}.").into());
+    }
+    let epoch = private_room.current_key().epoch + 1;
+    let key = RoomKey::generate(room, epoch);
+    let envelope = key.to_envelope();
+    let local_peer_id = self.local_peer_id();
+    let private_room = self
+      .private_rooms
+      .get_mut(room)
+      .expect("Checked by owned_room");
+    private_room.members.remove(&peer_id);
+    private_room.push_key(key);
+    let members: Vec<PeerId> = private_room
+      .members
+      .iter()
+      .filter(|member| **member != local_peer_id)
+      .copied()
+      .collect();
+    for member in members {
+      if let Err(er) = self.send_direct_envelope(member, &envelope) {
+        tracing::warn!("Failed to send the new key of {room} to {member}: {er}");
+      }
+    }
+    Ok(())
+  }
+
+  fn owned_room(&self, room: &str) -> Result<&PrivateRoom, Box<dyn Error>> {
+    let private_room = self
+      .private_rooms
+      .get(room)
+      .ok_or_else(|| format!("{room} is not a private room."))?;
+    if private_room.owner != self.local_peer_id() {
+      return Err(format!("Only the owner of {room} can manage its members.").into());
+    }
+    Ok(private_room)
+  }
+
+  /// Seal an envelope if the room is private.
+  pub(super) fn seal_for_room(
+    &self,
+    room: &str,
+    envelope: Envelope,
+  ) -> Result<Envelope, Box<dyn Error>> {
+    match self.private_rooms.get(room) {
+      Some(private_room) => private_room.current_key().seal(&envelope),
+      None => Ok(envelope),
+    }
+  }
+
+  /// Open a sealed envelope with any of the known keys of the room.
+  pub(super) fn open_for_room(&self, room: &str, envelope: &Envelope) -> Option<Envelope> {
+    let private_room = self.private_rooms.get(room)?;
+    private_room
+      .keys
+      .iter()
+      .rev()
+      .find_map(|key| key.open(envelope).ok())
+  }
+
+  /// Enter the private room we were last invited to, if any, before joining it. The other
+  /// invitations to it are declined.
+  pub(super) fn accept_invitation(&mut self, room: &str) {
+    let position = self.invitations.iter().rposition(|(name, _)| name == room);
+    if let Some(position) = position {
+      let (_, invitation) = self.invitations.remove(position);
+      self.decline_invitation(room, None);
+      self.private_rooms.insert(room.to_string(), invitation);
+    }
+  }
+
+  /// Forget the keys of a room we left, a later invitation or join starts afresh.
+  pub(super) fn leave_private_room(&mut self, room: &str) {
+    self.private_rooms.remove(room);
+  }
+
+  /// Rotate the key of a private room we are in, or keep an invitation to one until joined.
+  pub(super) fn handle_room_key(
+    &mut self,
+    peer_id: PeerId,
+    envelope: &Envelope,
+  ) -> Result<Option<ChatEvent>, Box<dyn Error>> {
+    let key = RoomKey::from_envelope(envelope)?;
+    let room = key.room.clone();
+    let epoch = key.epoch;
+    let invitation = self
+      .invitations
+      .iter_mut()
+      .find(|(name, invitation)| *name == room && invitation.owner == peer_id);
+    if let Some(private_room) = self.private_rooms.get_mut(&room) {
+      if !private_room.rotate(peer_id, key)? {
+        return Ok(None);
+      }
+    } else if let Some((_, invitation)) = invitation {
+      if !invitation.rotate(peer_id, key)? {
+        return Ok(None);
+      }
+    } else if self.is_joined(&room) {
+      return Err(format!("{room} is already joined as a public room.").into());
+    } else {
+      let sent = self
+        .invitations
+        .iter()
+        .filter(|(_, invitation)| invitation.owner == peer_id)
+        .count();
+      if sent >= MAX_PEER_INVITATIONS {
+        return Err(format!("Too many pending invitations from {peer_id}, {room} refused.").into());
+      }
+      if self.invitations.len() >= MAX_INVITATIONS {
+        return Err(format!("Too many pending invitations, {room} refused.").into());
+      }
+      let invitation = PrivateRoom {
+        owner: peer_id,
+        keys: vec![key],
+        members: BTreeSet::new(),
+      };
+      self.invitations.push((room.clone(), invitation));
+    }
+    Ok(Some(ChatEvent::RoomKeyReceived {
+      room,
+      peer_id,
+      epoch,
+    }))
+  }
+}
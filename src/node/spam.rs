@@ -0,0 +1,171 @@
+use libp2p::{
+  gossipsub::{self, TopicScoreParams},
+  PeerId,
+};
+use std::{
+  collections::HashMap,
+  fmt,
+  time::{Duration, Instant},
+};
+
+use super::{ChatEvent, ChatNode};
+
+/// Authors tracked before forgetting those quiet for a whole window.
+const MAX_AUTHORS: usize = 1_024;
+/// Nickname records accepted from each author on the presence topic. They are announced again
+/// to each new peer, bursts are expected on joining a network.
+pub(super) const PRESENCE_MESSAGES: u32 = 30;
+pub(super) const PRESENCE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Why a gossipsub message was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+  /// Undecodable, or failing the checks of its topic.
+  Malformed(String),
+  /// Its author published more messages than the rate limit allows.
+  RateLimited,
+}
+
+impl Rejection {
+  /// Label of the rejection in the metrics.
+  pub fn label(&self) -> &'static str {
+    match self {
+      Rejection::Malformed(_) => "malformed",
+      Rejection::RateLimited => "rate_limited",
+    }
+  }
+}
+
+impl fmt::Display for Rejection {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Rejection::Malformed(er) => write!(f, "malformed: {er}"),
+      Rejection::RateLimited => write!(f, "over the rate limit"),
+    }
+  }
+}
+
+/// Outcome of [`PublishLimit::check`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Limit {
+  Within,
+  /// The first message over the limit in the window.
+  Reached,
+  Over,
+}
+
+/// Room messages accepted from each author in a fixed window.
+pub(super) struct PublishLimit {
+  messages: u32,
+  window: Duration,
+  /// Start of the current window of each author, and its messages since.
+  authors: HashMap<PeerId, (Instant, u32)>,
+}
+
+impl PublishLimit {
+  /// No limit if `messages` is 0.
+  pub(super) fn new(messages: u32, window: Duration) -> Self {
+    PublishLimit {
+      messages,
+      window,
+      authors: HashMap::new(),
+    }
+  }
+
+  /// Count a message of the author.
+  pub(super) fn check(&mut self, author: PeerId, now: Instant) -> Limit {
+    if self.messages == 0 {
+      return Limit::Within;
+    }
+    if self.authors.len() >= MAX_AUTHORS {
+      let window = self.window;
+      self
+        .authors
+        .retain(|_, (start, _)| now.duration_since(*start) < window);
+    }
+    let (start, count) = self.authors.entry(author).or_insert((now, 0));
+    if now.duration_since(*start) >= self.window {
+      *start = now;
+      *count = 0;
+    }
+    *count += 1;
+    if *count <= self.messages {
+      Limit::Within
+    } else if *count == self.messages + 1 {
+      Limit::Reached
+    } else {
+      Limit::Over
+    }
+  }
+}
+
+/// Scoring of the peers of a room: rewarded for their time in the mesh and the messages they
+/// deliver first, penalized for the invalid ones.
+fn room_score_params() -> TopicScoreParams {
+  TopicScoreParams {
+    topic_weight: 1.0,
+    // Up to +6 after an hour in the mesh
+    time_in_mesh_weight: 0.1,
+    time_in_mesh_quantum: Duration::from_secs(60),
+    time_in_mesh_cap: 60.0,
+    first_message_deliveries_weight: 1.0,
+    first_message_deliveries_decay: gossipsub::score_parameter_decay(Duration::from_secs(10 * 60)),
+    first_message_deliveries_cap: 10.0,
+    // Rooms may be quiet for hours, peers are not expected to deliver any message
+    mesh_message_deliveries_weight: 0.0,
+    mesh_failure_penalty_weight: 0.0,
+    // Squared: 3 invalid messages get a peer graylisted, for longer the more it sent
+    invalid_message_deliveries_weight: -10.0,
+    invalid_message_deliveries_decay: gossipsub::score_parameter_decay(Duration::from_secs(
+      60 * 60,
+    )),
+    ..Default::default()
+  }
+}
+
+impl ChatNode {
+  /// Score the peers of a topic we subscribed to.
+  pub(super) fn score_room(&mut self, topic: gossipsub::IdentTopic) {
+    let gossipsub = &mut self.swarm.behaviour_mut().gossipsub;
+    if let Err(er) = gossipsub.set_topic_params(topic, room_score_params()) {
+      tracing::warn!("Failed to score the room: {er}");
+    }
+  }
+
+  /// Stop scoring the peers of a room we left. gossipsub cannot forget the params of a topic,
+  /// they are weighted 0 instead.
+  pub(super) fn unscore_room(&mut self, topic: gossipsub::IdentTopic) {
+    let params = TopicScoreParams {
+      topic_weight: 0.0,
+      ..room_score_params()
+    };
+    let gossipsub = &mut self.swarm.behaviour_mut().gossipsub;
+    if let Err(er) = gossipsub.set_topic_params(topic, params) {
+      tracing::warn!("Failed to unscore the room: {er}");
+    }
+  }
+
+  /// Log and count a rejected message.
+  pub(super) fn handle_rejection(
+    &mut self,
+    propagation_source: PeerId,
+    author: PeerId,
+    topic: gossipsub::TopicHash,
+    rejection: Rejection,
+  ) -> ChatEvent {
+    self
+      .chat_metrics
+      .messages_rejected
+      .get_or_create(&vec![("reason", rejection.label())])
+      .inc();
+    tracing::debug!(
+      "Rejected a message of {author} in {topic} from {propagation_source}: {rejection}"
+    );
+    ChatEvent::MessageRejected {
+      propagation_source,
+      author,
+      room: topic.into_string(),
+      reason: rejection,
+    }
+  }
+}
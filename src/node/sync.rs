@@ -0,0 +1,356 @@
+use libp2p::{
+  gossipsub::{self, MessageId},
+  identity::Keypair,
+  request_response::{self, OutboundFailure, OutboundRequestId},
+  PeerId,
+};
+use std::{
+  collections::{HashMap, HashSet, VecDeque},
+  error::Error,
+  io,
+  sync::{Arc, Mutex},
+};
+
+use super::{ChatEvent, ChatNode};
+use crate::utils::{
+  history::HistoryRecord,
+  msg::{message_id, now, Envelope, Kind, SignedMessage},
+  nick::PRESENCE_TOPIC,
+  sync::{SyncRequest, SyncResponse, MAX_SYNC_MESSAGES, SYNC_CLOCK_SKEW_MS},
+};
+
+/// Members of a room asked for its history at once.
+const MAX_SYNC_PEERS: usize = 3;
+/// Signed messages kept until they are stored.
+const MAX_SIGNED: usize = 1_024;
+
+/// Keeps the signatures of the messages of the rooms, which gossipsub does not hand over, so that
+/// they can be synced along with the messages.
+#[derive(Clone)]
+pub struct Signatures {
+  keypair: Keypair,
+  pending: Arc<Mutex<PendingSignatures>>,
+}
+
+#[derive(Default)]
+struct PendingSignatures {
+  order: VecDeque<MessageId>,
+  messages: HashMap<MessageId, SignedMessage>,
+}
+
+impl Signatures {
+  pub(super) fn new(keypair: Keypair) -> Self {
+    Signatures {
+      keypair,
+      pending: Default::default(),
+    }
+  }
+
+  /// Id of a message. Ours are signed again as they are published, as gossipsub does.
+  pub(super) fn message_id(&self, message: &gossipsub::Message) -> MessageId {
+    let id = message_id(message);
+    let own = message.source == Some(self.keypair.public().to_peer_id());
+    if let Some(seqno) = message.sequence_number.filter(|_| own) {
+      let room = message.topic.as_str();
+      match SignedMessage::sign(&self.keypair, room, seqno, message.data.clone()) {
+        Ok(signed) => self.insert(id.clone(), signed),
+        Err(er) => tracing::warn!("Failed to sign message {id}: {er}"),
+      }
+    }
+    id
+  }
+
+  /// The signed message, taken once to be stored.
+  pub(super) fn take(&self, id: &MessageId) -> Option<SignedMessage> {
+    let mut pending = self.pending.lock().expect("signatures lock poisoned");
+    pending.messages.remove(id)
+  }
+
+  fn insert(&self, id: MessageId, signed: SignedMessage) {
+    let mut pending = self.pending.lock().expect("signatures lock poisoned");
+    if pending.messages.insert(id.clone(), signed).is_none() {
+      pending.order.push_back(id);
+    }
+    while pending.order.len() > MAX_SIGNED {
+      if let Some(oldest) = pending.order.pop_front() {
+        pending.messages.remove(&oldest);
+      }
+    }
+  }
+}
+
+impl gossipsub::DataTransform for Signatures {
+  fn inbound_transform(
+    &self,
+    raw_message: gossipsub::RawMessage,
+  ) -> Result<gossipsub::Message, io::Error> {
+    let message = gossipsub::Message {
+      source: raw_message.source,
+      data: raw_message.data,
+      sequence_number: raw_message.sequence_number,
+      topic: raw_message.topic,
+    };
+    // Presence records are signed on their own, and never synced
+    let chat = message.topic.as_str() != PRESENCE_TOPIC;
+    if let (Some(seqno), Some(signature), true) =
+      (message.sequence_number, raw_message.signature, chat)
+    {
+      let signed = SignedMessage {
+        seqno,
+        data: message.data.clone(),
+        signature,
+        key: raw_message.key,
+      };
+      self.insert(message_id(&message), signed);
+    }
+    Ok(message)
+  }
+
+  fn outbound_transform(
+    &self,
+    _topic: &gossipsub::TopicHash,
+    data: Vec<u8>,
+  ) -> Result<Vec<u8>, io::Error> {
+    Ok(data)
+  }
+}
+
+/// Requests for the messages missed in the joined rooms.
+#[derive(Default)]
+pub(super) struct Syncs {
+  /// Connected members asked for the history of each room.
+  asked: HashMap<String, HashSet<PeerId>>,
+  inflight: HashMap<OutboundRequestId, SyncRequest>,
+}
+
+impl ChatNode {
+  /// Ask the members of a room we are connected to for the messages we missed.
+  pub(super) fn sync_room(&mut self, room: &str) {
+    let topic = gossipsub::IdentTopic::new(room).hash();
+    let members = self
+      .swarm
+      .behaviour()
+      .gossipsub
+      .all_peers()
+      .filter(|(_, topics)| topics.contains(&&topic))
+      .map(|(peer_id, _)| *peer_id)
+      .collect::<Vec<_>>();
+    for peer_id in members {
+      self.request_sync(peer_id, room);
+    }
+  }
+
+  /// Ask a member of a room for the messages we missed, unless enough members already were.
+  pub(super) fn request_sync(&mut self, peer_id: PeerId, room: &str) {
+    if self.history.is_none() || !self.is_joined(room) {
+      return;
+    }
+    let asked = self.syncs.asked.entry(room.to_string()).or_default();
+    if asked.len() >= MAX_SYNC_PEERS || !asked.insert(peer_id) {
+      return;
+    }
+    let last = match self.history(room, 1) {
+      Ok(mut records) => records.pop(),
+      Err(er) => {
+        return tracing::warn!("Failed to read the history of [{room}]: {er}");
+      }
+    };
+    let request = SyncRequest {
+      room: room.to_string(),
+      after: last.as_ref().map(|record| record.message_id.clone()),
+      since: last.map(|record| record.received_at.saturating_sub(SYNC_CLOCK_SKEW_MS)),
+      limit: MAX_SYNC_MESSAGES,
+    };
+    let request_id = self
+      .swarm
+      .behaviour_mut()
+      .sync
+      .send_request(&peer_id, request.clone());
+    self.syncs.inflight.insert(request_id, request);
+  }
+
+  /// Members may have received new messages by the time they reconnect.
+  pub(super) fn handle_sync_disconnection(&mut self, peer_id: &PeerId) {
+    for asked in self.syncs.asked.values_mut() {
+      asked.remove(peer_id);
+    }
+  }
+
+  pub(super) fn handle_sync_left(&mut self, room: &str) {
+    self.syncs.asked.remove(room);
+  }
+
+  pub(super) fn handle_sync_event(
+    &mut self,
+    event: request_response::Event<SyncRequest, SyncResponse>,
+  ) -> Option<ChatEvent> {
+    match event {
+      request_response::Event::Message {
+        peer,
+        message: request_response::Message::Request {
+          request, channel, ..
+        },
+      } => {
+        let response = match self.history_to_sync(&request) {
+          Ok(records) => SyncResponse::Messages(records),
+          Err(er) => SyncResponse::Refused(er.to_string()),
+        };
+        if let Err(er) = self
+          .swarm
+          .behaviour_mut()
+          .sync
+          .send_response(channel, response)
+        {
+          tracing::warn!(
+            "Failed to send the history of [{}] to {peer}: {er:?}",
+            request.room
+          );
+        }
+        None
+      }
+      request_response::Event::Message {
+        peer,
+        message:
+          request_response::Message::Response {
+            request_id,
+            response,
+          },
+      } => {
+        let request = self.syncs.inflight.remove(&request_id)?;
+        match response {
+          SyncResponse::Messages(records) => self.merge_history(peer, request, records),
+          SyncResponse::Refused(er) => {
+            tracing::warn!("{peer} refused to sync [{}]: {er}", request.room);
+            None
+          }
+        }
+      }
+      request_response::Event::OutboundFailure {
+        peer,
+        request_id,
+        error,
+      } => {
+        let request = self.syncs.inflight.remove(&request_id)?;
+        // Peers running an older version cannot sync
+        if !matches!(error, OutboundFailure::UnsupportedProtocols) {
+          tracing::warn!("Failed to sync [{}] from {peer}: {error}", request.room);
+        }
+        None
+      }
+      request_response::Event::InboundFailure { peer, error, .. } => {
+        tracing::warn!("Failed to send history to {peer}: {error}");
+        None
+      }
+      request_response::Event::ResponseSent { .. } => None,
+    }
+  }
+
+  /// Signed messages of a joined room asked by a peer, as published: sealed if the room is
+  /// private.
+  fn history_to_sync(&self, request: &SyncRequest) -> Result<Vec<HistoryRecord>, Box<dyn Error>> {
+    if !self.is_joined(&request.room) {
+      return Err(format!("Not a member of {}.", request.room).into());
+    }
+    let history = self.history.as_ref().ok_or("History is disabled.")?;
+    let limit = request.limit.min(MAX_SYNC_MESSAGES);
+    let records = history.after(
+      &request.room,
+      request.after.as_deref(),
+      request.since,
+      limit,
+    )?;
+    // Those stored by older versions cannot be checked by the peer
+    let signed = records.into_iter().filter_map(|mut record| {
+      match Envelope::decode(&record.signed.as_ref()?.data) {
+        Ok(envelope) => {
+          record.envelope = envelope;
+          Some(record)
+        }
+        Err(er) => {
+          tracing::warn!(
+            "Not syncing message {} of {}: {er}",
+            record.message_id,
+            record.room
+          );
+          None
+        }
+      }
+    });
+    Ok(signed.collect())
+  }
+
+  /// Store the messages we did not have yet, and report them.
+  fn merge_history(
+    &self,
+    peer_id: PeerId,
+    request: SyncRequest,
+    records: Vec<HistoryRecord>,
+  ) -> Option<ChatEvent> {
+    let history = self.history.as_ref()?;
+    let mut merged = Vec::new();
+    for mut record in records.into_iter().take(request.limit) {
+      let author = (record.room == request.room)
+        .then(|| record.author.parse::<PeerId>().ok())
+        .flatten();
+      let Some(author) = author else {
+        tracing::warn!("Ignored an invalid message synced from {peer_id}");
+        continue;
+      };
+      // Blocked peers do not get in through the history of others
+      if !self.is_allowed(&author) {
+        continue;
+      }
+      // Only what the author signed is trusted, not what the peer tells of it
+      match self.verify_synced(&request.room, &author, &record) {
+        Ok(envelope) => record.envelope = envelope,
+        Err(er) => {
+          tracing::warn!(
+            "Ignored message {} synced from {peer_id}: {er}",
+            record.message_id
+          );
+          continue;
+        }
+      }
+      // The clock of the peer may be ahead of ours
+      record.received_at = record.received_at.min(now());
+      match history.insert(&record) {
+        Ok(true) => merged.push(record),
+        Ok(false) => {}
+        Err(er) => {
+          tracing::warn!(
+            "Failed to store message {} in the history: {er}",
+            record.message_id
+          );
+        }
+      }
+    }
+    (!merged.is_empty()).then_some(ChatEvent::HistorySynced {
+      room: request.room,
+      peer_id,
+      records: merged,
+    })
+  }
+
+  /// The envelope signed by the author of a synced message, opened if the room is private.
+  fn verify_synced(
+    &self,
+    room: &str,
+    author: &PeerId,
+    record: &HistoryRecord,
+  ) -> Result<Envelope, Box<dyn Error>> {
+    let signed = record.signed.as_ref().ok_or("Not signed.")?;
+    let message = signed.verify(author, room)?;
+    if message_id(&message).to_string() != record.message_id {
+      return Err("Wrong message id.".into());
+    }
+    let envelope = Envelope::decode(&signed.data)?;
+    if envelope.kind != Kind::Encrypted {
+      if self.is_private(room) {
+        return Err("Unsealed message of a private room.".into());
+      }
+      return Ok(envelope);
+    }
+    // Left sealed when we do not have the key
+    Ok(self.open_for_room(room, &envelope).unwrap_or(envelope))
+  }
+}
@@ -0,0 +1,240 @@
+use futures::stream::StreamExt;
+use libp2p::{
+  autonat,
+  core::transport::ListenerId,
+  identify,
+  identity::Keypair,
+  kad::{self, store},
+  metrics::Registry,
+  multiaddr::Protocol,
+  ping, relay,
+  swarm::{behaviour::toggle::Toggle, NetworkBehaviour, SwarmEvent},
+  Multiaddr, PeerId, Swarm,
+};
+use std::{collections::HashMap, error::Error, sync::Arc, time::Duration};
+use tokio::{sync::watch, time::timeout};
+
+use crate::{
+  config::Config,
+  metrics::Metrics,
+  utils::{
+    peer::{dial_quic_first, parse_peer_id},
+    swarm,
+  },
+};
+
+/// Time given to the connections to close once shutting down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub mod health;
+
+use health::{Health, Status};
+
+/// Infrastructure behaviours only, chat peers use the DHT of a server to find each other.
+#[derive(NetworkBehaviour)]
+pub struct ServerBehaviour {
+  pub ping: ping::Behaviour,
+  pub identify: identify::Behaviour,
+  pub kademlia: kad::Behaviour<store::MemoryStore>,
+  pub autonat: autonat::Behaviour,
+  /// To dial the peers only reachable through a relay.
+  pub relay_client: relay::client::Behaviour,
+  pub relay: Toggle<relay::Behaviour>,
+}
+
+/// Events surfaced by [`ServerNode::next_event`].
+#[derive(Debug)]
+pub enum ServerEvent {
+  /// The node is listening on a new address (including the `/p2p/<peer id>` suffix).
+  ListenAddr(Multiaddr),
+  PeerConnected(PeerId),
+  PeerDisconnected(PeerId),
+  /// A peer has been identified and, if it speaks Kademlia, added to the DHT.
+  PeerIdentified(PeerId),
+  /// Any other swarm event, for logging.
+  Other(Box<SwarmEvent<ServerBehaviourEvent>>),
+}
+
+/// A bootstrap node without chat: no stdin, no gossipsub rooms.
+pub struct ServerNode {
+  swarm: Swarm<ServerBehaviour>,
+  listeners: Vec<ListenerId>,
+  health: watch::Sender<Health>,
+  metrics: Metrics,
+}
+
+impl ServerNode {
+  /// Start listening and bootstrap from the other servers of the configuration, if any.
+  pub fn new(keypair: Keypair, config: &Config) -> Result<Self, Box<dyn Error>> {
+    let local_peer_id = keypair.public().to_peer_id();
+    let websocket = config.network.ws_listener()?;
+    let mut registry = Registry::default();
+    let mut swarm = swarm::build(
+      keypair,
+      websocket.as_ref(),
+      &mut registry,
+      Duration::from_secs(config.network.idle_timeout_secs),
+      |key, relay_client| {
+        let query_timeout = Duration::from_secs(config.kademlia.query_timeout_secs);
+        Ok(ServerBehaviour {
+          ping: ping::Behaviour::default(),
+          identify: swarm::identify(key, &config.network.identify_protocol),
+          kademlia: swarm::kademlia(key, query_timeout),
+          autonat: swarm::autonat(key),
+          relay_client,
+          relay: swarm::relay_server(key, config.server.relay),
+        })
+      },
+    )?;
+
+    let external_addrs = config
+      .network
+      .external_addrs
+      .iter()
+      .map(|addr| addr.parse())
+      .collect::<Result<Vec<Multiaddr>, _>>()?;
+    let listeners = swarm::listen(
+      &mut swarm,
+      config.network.port,
+      config.network.quic,
+      websocket.as_ref(),
+      external_addrs,
+    )?;
+
+    if !config.network.bootstrap.is_empty() {
+      let mut bootstrap: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
+      for addr in &config.network.bootstrap {
+        let addr: Multiaddr = addr.parse()?;
+        let peer_id = parse_peer_id(&addr.to_string())?;
+        swarm
+          .behaviour_mut()
+          .kademlia
+          .add_address(&peer_id, addr.clone());
+        bootstrap.entry(peer_id).or_default().push(addr);
+      }
+      for (peer_id, addrs) in bootstrap {
+        if let Err(er) = swarm.dial(dial_quic_first(peer_id, addrs)) {
+          tracing::warn!("Failed to dial bootstrap peer {peer_id}: {er}");
+        }
+      }
+      if let Err(er) = swarm.behaviour_mut().kademlia.bootstrap() {
+        tracing::warn!("Failed to run Kademlia bootstrap: {er}");
+      }
+    }
+
+    let (health, _) = watch::channel(Health::new(local_peer_id));
+    Ok(ServerNode {
+      swarm,
+      listeners,
+      health,
+      metrics: Metrics::new(registry),
+    })
+  }
+
+  pub fn local_peer_id(&self) -> PeerId {
+    *self.swarm.local_peer_id()
+  }
+
+  pub fn swarm(&self) -> &Swarm<ServerBehaviour> {
+    &self.swarm
+  }
+
+  pub fn swarm_mut(&mut self) -> &mut Swarm<ServerBehaviour> {
+    &mut self.swarm
+  }
+
+  /// Health reports, updated as the swarm makes progress.
+  pub fn health(&self) -> watch::Receiver<Health> {
+    self.health.subscribe()
+  }
+
+  /// Prometheus metrics of the swarm.
+  pub fn metrics(&self) -> Arc<Registry> {
+    self.metrics.registry()
+  }
+
+  /// Report the node as stopping, so that the health checks fail while it shuts down, stop
+  /// accepting connections and close the established ones.
+  pub async fn shutdown(&mut self) {
+    self
+      .health
+      .send_modify(|health| health.status = Status::Stopping);
+    for listener_id in self.listeners.drain(..) {
+      self.swarm.remove_listener(listener_id);
+    }
+    let peers = self.swarm.connected_peers().copied().collect::<Vec<_>>();
+    for peer_id in peers {
+      let _ = self.swarm.disconnect_peer_id(peer_id);
+    }
+    // Peers that do not answer are dropped along with the swarm
+    let closed = timeout(SHUTDOWN_TIMEOUT, async {
+      while self.swarm.connected_peers().next().is_some() {
+        let event = self.swarm.select_next_some().await;
+        self.handle_swarm_event(event);
+      }
+    });
+    if closed.await.is_err() {
+      tracing::warn!("Connections still open after {SHUTDOWN_TIMEOUT:?}, closing anyway");
+    }
+  }
+
+  /// Drive the swarm until the next event worth reporting.
+  pub async fn next_event(&mut self) -> ServerEvent {
+    loop {
+      let event = self.swarm.select_next_some().await;
+      if let Some(event) = self.handle_swarm_event(event) {
+        return event;
+      }
+    }
+  }
+
+  fn handle_swarm_event(&mut self, event: SwarmEvent<ServerBehaviourEvent>) -> Option<ServerEvent> {
+    self.record_metrics(&event);
+    let local_peer_id = self.local_peer_id();
+    match event {
+      SwarmEvent::NewListenAddr { address, .. } => {
+        let address = address.with(Protocol::P2p(local_peer_id));
+        self.health.send_modify(|health| {
+          health.status = Status::Ok;
+          health.listen_addrs.push(address.to_string());
+        });
+        Some(ServerEvent::ListenAddr(address))
+      }
+      SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+        self.update_peers();
+        Some(ServerEvent::PeerConnected(peer_id))
+      }
+      SwarmEvent::ConnectionClosed { peer_id, .. } => {
+        self.update_peers();
+        Some(ServerEvent::PeerDisconnected(peer_id))
+      }
+      SwarmEvent::Behaviour(ServerBehaviourEvent::Identify(identify::Event::Received {
+        peer_id,
+        info,
+      })) => {
+        swarm::add_identified(&mut self.swarm.behaviour_mut().kademlia, peer_id, &info);
+        Some(ServerEvent::PeerIdentified(peer_id))
+      }
+      event => Some(ServerEvent::Other(Box::new(event))),
+    }
+  }
+
+  fn record_metrics(&self, event: &SwarmEvent<ServerBehaviourEvent>) {
+    self.metrics.record(event);
+    let SwarmEvent::Behaviour(event) = event else {
+      return;
+    };
+    match event {
+      ServerBehaviourEvent::Ping(event) => self.metrics.record(event),
+      ServerBehaviourEvent::Identify(event) => self.metrics.record(event),
+      ServerBehaviourEvent::Kademlia(event) => self.metrics.record(event),
+      ServerBehaviourEvent::Relay(event) => self.metrics.record(event),
+      ServerBehaviourEvent::Autonat(_) | ServerBehaviourEvent::RelayClient(_) => {}
+    }
+  }
+
+  fn update_peers(&mut self) {
+    let peers = self.swarm.connected_peers().count();
+    self.health.send_modify(|health| health.peers = peers);
+  }
+}
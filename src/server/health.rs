@@ -0,0 +1,95 @@
+use libp2p::{metrics::Registry, PeerId};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::{TcpListener, TcpStream},
+  sync::watch,
+};
+
+use crate::metrics::{self, METRICS_PATH};
+
+pub const HEALTH_PATH: &str = "/health";
+const JSON: &str = "application/json";
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+  /// Not listening yet.
+  Starting,
+  Ok,
+  /// Shutting down after a signal.
+  Stopping,
+}
+
+/// Body of the health endpoint.
+#[derive(Serialize, Debug, Clone)]
+pub struct Health {
+  pub status: Status,
+  pub peer_id: String,
+  /// Number of connected peers.
+  pub peers: usize,
+  pub listen_addrs: Vec<String>,
+}
+
+impl Health {
+  pub fn new(peer_id: PeerId) -> Self {
+    Health {
+      status: Status::Starting,
+      peer_id: peer_id.to_string(),
+      peers: 0,
+      listen_addrs: Vec::new(),
+    }
+  }
+}
+
+/// Answer `GET /health` with the latest report, `200` when it is ok and `503` otherwise, and
+/// `GET /metrics` with the Prometheus metrics.
+pub async fn serve(listener: TcpListener, health: watch::Receiver<Health>, metrics: Arc<Registry>) {
+  loop {
+    match listener.accept().await {
+      Ok((stream, _)) => {
+        let health = health.borrow().clone();
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+          if let Err(er) = respond(stream, health, &metrics).await {
+            tracing::warn!("Failed to answer a health check: {er}");
+          }
+        });
+      }
+      Err(er) => tracing::warn!("Failed to accept a health check: {er}"),
+    }
+  }
+}
+
+async fn respond(mut stream: TcpStream, health: Health, metrics: &Registry) -> std::io::Result<()> {
+  // The request line is all we need
+  let mut buf = [0u8; 1024];
+  let len = stream.read(&mut buf).await?;
+  let request = String::from_utf8_lossy(&buf[..len]);
+  let mut parts = request.split_whitespace();
+  let (status, content_type, body) = match (parts.next(), parts.next()) {
+    (Some("GET"), Some(HEALTH_PATH)) => {
+      let status = match health.status {
+        Status::Ok => "200 OK",
+        Status::Starting | Status::Stopping => "503 Service Unavailable",
+      };
+      let body = serde_json::to_string(&health).expect("Health is always serializable");
+      (status, JSON, body)
+    }
+    (Some("GET"), Some(METRICS_PATH)) => {
+      ("200 OK", metrics::CONTENT_TYPE, metrics::encode(metrics))
+    }
+    _ => (
+      "404 Not Found",
+      JSON,
+      r#"{"error":"not found"}"#.to_string(),
+    ),
+  };
+  let response = format!(
+    "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+    body.len()
+  );
+  stream.write_all(response.as_bytes()).await?;
+  stream.shutdown().await
+}
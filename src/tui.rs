@@ -0,0 +1,339 @@
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use futures::StreamExt;
+use libp2p::PeerId;
+use ratatui::{
+  layout::{Constraint, Layout, Rect},
+  style::{Modifier, Style, Stylize},
+  text::Line as TextLine,
+  widgets::{Block, List, ListItem, Paragraph, Wrap},
+  Frame,
+};
+use rust_libp2p_chat::{
+  control::Call,
+  utils::{api::ApiEvent, cmd::Command},
+  ChatEvent, ChatNode,
+};
+use std::{
+  collections::{BTreeSet, HashMap, VecDeque},
+  error::Error,
+  io,
+};
+use tokio::{
+  select,
+  sync::{
+    broadcast,
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+  },
+};
+use tracing_subscriber::EnvFilter;
+
+use crate::{broadcast_event, event_lines, handle_command, Line};
+
+/// Lines kept in each pane.
+const SCROLLBACK: usize = 1_000;
+/// Lines scrolled by PageUp and PageDown.
+const PAGE: usize = 10;
+const HELP: &str = " Enter: send · Tab: next room · PgUp/PgDn: scroll · Esc: quit ";
+
+/// Send the logs to the status pane, as writing them to stderr would garble the screen.
+///
+/// To be called before anything logs.
+pub fn capture_logs() -> UnboundedReceiver<String> {
+  let (tx, rx) = mpsc::unbounded_channel();
+  let _ = tracing_subscriber::fmt()
+    .with_env_filter(EnvFilter::from_default_env())
+    .with_ansi(false)
+    .without_time()
+    .with_writer(move || LogWriter(tx.clone()))
+    .try_init();
+  rx
+}
+
+struct LogWriter(UnboundedSender<String>);
+
+impl io::Write for LogWriter {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    // Logs written once the interface is closed are dropped
+    let _ = self
+      .0
+      .send(String::from_utf8_lossy(buf).trim_end().to_string());
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// Run the full-screen interface until Esc or Ctrl-C.
+pub async fn run(
+  mut node: ChatNode,
+  current: Option<String>,
+  silent: bool,
+  mut logs: UnboundedReceiver<String>,
+  mut api: mpsc::Receiver<Call>,
+  events: broadcast::Sender<ApiEvent>,
+) -> Result<(), Box<dyn Error>> {
+  let mut terminal = ratatui::try_init()?;
+  let mut app = App::new(current);
+  let mut keys = EventStream::new();
+  let result = loop {
+    if let Err(er) = terminal.draw(|frame| app.draw(frame, &node)) {
+      break Err(er.into());
+    }
+    select! {
+      Some(key) = keys.next() => match key {
+        Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+          if !app.handle_key(&mut node, key) {
+            break Ok(());
+          }
+        }
+        Ok(_) => {}
+        Err(er) => break Err(er.into()),
+      },
+      event = node.next_event() => {
+        broadcast_event(&node, &events, &event);
+        match &event {
+          ChatEvent::PeerConnected { peer_id, .. } => {
+            app.peers.insert(*peer_id);
+          }
+          ChatEvent::PeerDisconnected(peer_id) if !node.swarm().is_connected(peer_id) => {
+            app.peers.remove(peer_id);
+          }
+          _ => {}
+        }
+        for line in event_lines(&node, event, silent) {
+          app.push(line);
+        }
+      }
+      Some(log) = logs.recv() => app.push(Line::Status(format!("📝 {log}"))),
+      Some((request, reply)) = api.recv() => {
+        // The HTTP client may be gone
+        let _ = reply.send(node.execute(request));
+      }
+    }
+  };
+  ratatui::restore();
+  result
+}
+
+struct App {
+  current: Option<String>,
+  /// Messages of each room. Direct messages and replies to commands go to the current one.
+  messages: HashMap<String, VecDeque<String>>,
+  /// Messages received in each room since it was last shown.
+  unread: HashMap<String, usize>,
+  status: VecDeque<String>,
+  peers: BTreeSet<PeerId>,
+  input: String,
+  /// Rows of the messages pane scrolled up from the bottom.
+  scroll: usize,
+}
+
+impl App {
+  fn new(current: Option<String>) -> Self {
+    App {
+      current,
+      messages: HashMap::new(),
+      unread: HashMap::new(),
+      status: VecDeque::new(),
+      peers: BTreeSet::new(),
+      input: String::new(),
+      scroll: 0,
+    }
+  }
+
+  fn push(&mut self, line: Line) {
+    match line {
+      Line::Room(room, text) => {
+        if self.current.as_ref() != Some(&room) {
+          *self.unread.entry(room.clone()).or_default() += 1;
+        }
+        push_bounded(self.messages.entry(room).or_default(), text);
+      }
+      Line::Chat(text) => {
+        let room = self.current.clone().unwrap_or_default();
+        push_bounded(self.messages.entry(room).or_default(), text);
+      }
+      Line::Status(text) => push_bounded(&mut self.status, text),
+    }
+  }
+
+  fn show(&mut self, room: Option<String>) {
+    if let Some(room) = &room {
+      self.unread.remove(room);
+    }
+    self.current = room;
+    self.scroll = 0;
+  }
+
+  /// Returns `false` once the user wants to quit.
+  fn handle_key(&mut self, node: &mut ChatNode, key: KeyEvent) -> bool {
+    match key.code {
+      KeyCode::Esc => return false,
+      KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+      KeyCode::Char(c) => self.input.push(c),
+      KeyCode::Backspace => {
+        self.input.pop();
+      }
+      KeyCode::Enter => {
+        let line = std::mem::take(&mut self.input);
+        if !line.trim().is_empty() {
+          self.submit(node, &line);
+        }
+      }
+      KeyCode::Tab | KeyCode::BackTab => {
+        let rooms = node.rooms().map(str::to_string).collect::<Vec<_>>();
+        let position = rooms
+          .iter()
+          .position(|room| self.current.as_ref() == Some(room));
+        let next = match (position, key.code) {
+          (Some(i), KeyCode::Tab) => (i + 1) % rooms.len(),
+          (Some(i), _) => (i + rooms.len() - 1) % rooms.len(),
+          (None, _) => 0,
+        };
+        self.show(rooms.get(next).cloned());
+      }
+      KeyCode::Up => self.scroll += 1,
+      KeyCode::Down => self.scroll = self.scroll.saturating_sub(1),
+      KeyCode::PageUp => self.scroll += PAGE,
+      KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(PAGE),
+      KeyCode::End => self.scroll = 0,
+      _ => {}
+    }
+    true
+  }
+
+  fn submit(&mut self, node: &mut ChatNode, line: &str) {
+    match Command::parse(line) {
+      // Echoed, as the input line is cleared
+      Ok(Command::Say(text)) if self.current.is_some() => {
+        let room = self.current.clone().unwrap_or_default();
+        match node.send_text(&room, text.as_str()) {
+          Ok(_) => self.push(Line::Room(room, format!("💬 You: {text}"))),
+          Err(er) => self.push(Line::Chat(format!(
+            "❌ Failed to publish the message: {er}"
+          ))),
+        }
+      }
+      Ok(cmd) => {
+        let mut current = self.current.clone();
+        let replies = handle_command(node, &mut current, cmd);
+        if current != self.current {
+          self.show(current);
+        }
+        for reply in replies {
+          self.push(Line::Chat(reply));
+        }
+      }
+      Err(er) => self.push(Line::Chat(format!("❌ {er}"))),
+    }
+  }
+
+  fn draw(&mut self, frame: &mut Frame, node: &ChatNode) {
+    let [main, status, input] = Layout::vertical([
+      Constraint::Min(5),
+      Constraint::Length(8),
+      Constraint::Length(3),
+    ])
+    .areas(frame.area());
+    let [rooms, messages, peers] = Layout::horizontal([
+      Constraint::Length(24),
+      Constraint::Min(20),
+      Constraint::Length(32),
+    ])
+    .areas(main);
+
+    let items = node
+      .rooms()
+      .map(|room| {
+        let mut label = room.to_string();
+        if node.is_private(room) {
+          label.push_str(" 🔒");
+        }
+        if let Some(unread) = self.unread.get(room) {
+          label.push_str(&format!(" ({unread})"));
+        }
+        let item = ListItem::new(label);
+        if self.current.as_deref() == Some(room) {
+          item.add_modifier(Modifier::REVERSED)
+        } else {
+          item
+        }
+      })
+      .collect::<Vec<_>>();
+    frame.render_widget(
+      List::new(items).block(Block::bordered().title(" Rooms ")),
+      rooms,
+    );
+
+    let room = self.current.clone().unwrap_or_default();
+    let mut title = format!(" [{room}] ");
+    if self.scroll > 0 {
+      title.push_str(&format!("↑{} ", self.scroll));
+    }
+    let lines = self.messages.get(&room).cloned().unwrap_or_default();
+    let block = Block::bordered().title(title);
+    self.scroll = render_tail(frame, messages, lines, self.scroll, block);
+
+    let items = self
+      .peers
+      .iter()
+      .map(|peer_id| {
+        let id = peer_id.to_string();
+        let short = &id[id.len().saturating_sub(8)..];
+        ListItem::new(match node.nickname_of(peer_id) {
+          Some(nick) => format!("{nick} (…{short})"),
+          None => format!("…{short}"),
+        })
+      })
+      .collect::<Vec<_>>();
+    let title = format!(" Peers ({}) ", self.peers.len());
+    frame.render_widget(
+      List::new(items).block(Block::bordered().title(title)),
+      peers,
+    );
+
+    let block = Block::bordered()
+      .title(" Status ")
+      .style(Style::new().dim());
+    let _ = render_tail(frame, status, self.status.clone(), 0, block);
+
+    let block = Block::bordered().title(" > ").title_bottom(HELP);
+    frame.render_widget(Paragraph::new(self.input.as_str()).block(block), input);
+    let width = TextLine::raw(self.input.as_str()).width() as u16;
+    frame.set_cursor_position((
+      input.x + 1 + width.min(input.width.saturating_sub(2)),
+      input.y + 1,
+    ));
+  }
+}
+
+fn push_bounded(lines: &mut VecDeque<String>, line: String) {
+  if lines.len() == SCROLLBACK {
+    lines.pop_front();
+  }
+  lines.push_back(line);
+}
+
+/// Wrapped lines, the last one at the bottom of the pane unless scrolled up by `scroll` rows.
+///
+/// Returns the rows actually scrolled, as there may be fewer.
+fn render_tail(
+  frame: &mut Frame,
+  area: Rect,
+  lines: VecDeque<String>,
+  scroll: usize,
+  block: Block,
+) -> usize {
+  let inner = block.inner(area);
+  let paragraph = Paragraph::new(lines.into_iter().map(TextLine::from).collect::<Vec<_>>())
+    .wrap(Wrap { trim: false });
+  let hidden = paragraph
+    .line_count(inner.width)
+    .saturating_sub(inner.height as usize);
+  let scroll = scroll.min(hidden);
+  let offset = (hidden - scroll).min(u16::MAX as usize) as u16;
+  frame.render_widget(paragraph.block(block).scroll((offset, 0)), area);
+  scroll
+}
@@ -1,2 +1,14 @@
+pub mod api;
+pub mod blocklist;
+pub mod cmd;
+pub mod crypto;
+pub mod dm;
+pub mod history;
+pub mod keystore;
 pub mod msg;
+pub mod nick;
 pub mod peer;
+pub mod room;
+pub mod swarm;
+pub mod sync;
+pub mod ws;
@@ -0,0 +1,195 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{history::HistoryRecord, msg::Kind};
+
+/// A command sent by a script, one JSON object per line.
+///
+/// `{"id": 1, "command": "publish", "room": "desnet-the-room", "text": "hi"}`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ApiRequest {
+  /// Any JSON value, echoed in the [`ApiResult`] of the command.
+  #[serde(default)]
+  pub id: Option<Value>,
+  #[serde(flatten)]
+  pub command: ApiCommand,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ApiCommand {
+  /// Send a text to a room, sealed if the room is private.
+  Publish {
+    room: String,
+    text: String,
+  },
+  Join {
+    room: String,
+  },
+  Leave {
+    room: String,
+  },
+  /// Send a direct message to a peer, by PeerId or nickname.
+  Dm {
+    peer: String,
+    text: String,
+  },
+  Nick {
+    nickname: String,
+  },
+  /// List the joined rooms.
+  Rooms,
+  /// List the connected peers.
+  Peers,
+  /// Show our PeerId, nickname and listen addresses.
+  Info,
+  /// Refuse the connections and messages of a peer, by PeerId or nickname.
+  Block {
+    peer: String,
+  },
+  Unblock {
+    peer: String,
+  },
+  /// List the blocked peers.
+  Blocked,
+  /// The last messages of a room, oldest first.
+  History {
+    room: String,
+    /// 20 by default.
+    #[serde(default)]
+    limit: Option<usize>,
+  },
+}
+
+/// Outcome of an [`ApiRequest`]. Only the fields relevant to the command are set.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ApiResult {
+  /// `null` when the request had none.
+  pub id: Option<Value>,
+  pub ok: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+  /// Id of the published message.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub message_id: Option<String>,
+  /// Local handle of a direct message, as in its `direct_delivered` or `direct_failed` event.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub dm_id: Option<u64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub rooms: Option<Vec<RoomInfo>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub peers: Option<Vec<PeerInfo>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub node: Option<NodeInfo>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub history: Option<Vec<HistoryRecord>>,
+}
+
+impl ApiResult {
+  pub fn failed(id: Option<Value>, error: impl ToString) -> Self {
+    ApiResult {
+      id,
+      ok: false,
+      error: Some(error.to_string()),
+      ..Default::default()
+    }
+  }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct RoomInfo {
+  pub name: String,
+  pub private: bool,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PeerInfo {
+  pub peer_id: String,
+  pub nickname: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct NodeInfo {
+  pub peer_id: String,
+  pub nickname: Option<String>,
+  /// Ending with `/p2p/<peer id>`.
+  pub listen_addrs: Vec<String>,
+}
+
+/// What a script is told, one JSON object per line. PeerIds are base58 strings.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ApiEvent {
+  Result(ApiResult),
+  /// The node is listening on a new address, ending with `/p2p/<peer id>`.
+  ListenAddr {
+    address: String,
+  },
+  PeerConnected {
+    peer_id: String,
+    /// Remote address, for direct connections.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<String>,
+    /// Relay the connection goes through.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    relay: Option<String>,
+  },
+  PeerDisconnected {
+    peer_id: String,
+  },
+  /// A peer is known by a new nickname.
+  NicknameChanged {
+    peer_id: String,
+    nickname: String,
+  },
+  /// Number of reachable bootstrap peers changed.
+  Bootstrap {
+    reachable: usize,
+    total: usize,
+  },
+  /// Kademlia bootstrap reached a peer.
+  Bootstrapped {
+    peer_id: String,
+  },
+  /// A message of a joined room.
+  Message {
+    room: String,
+    message_id: String,
+    author: String,
+    /// Nickname of the author, verified by its signed record.
+    nickname: Option<String>,
+    /// Nickname the author put in the message, which anyone can spoof.
+    claimed_nickname: Option<String>,
+    /// Unix timestamp in milliseconds, as claimed by the author.
+    timestamp: u64,
+    /// `encrypted` when we do not have the key of the private room, the body is then sealed.
+    kind: Kind,
+    body: String,
+  },
+  DirectMessage {
+    peer_id: String,
+    nickname: Option<String>,
+    claimed_nickname: Option<String>,
+    timestamp: u64,
+    kind: Kind,
+    body: String,
+  },
+  /// A message of a room was rejected. Only the first message over the rate limit in a window
+  /// is reported.
+  MessageRejected {
+    room: String,
+    author: String,
+    /// Peer the message came from.
+    peer_id: String,
+    reason: String,
+  },
+  DirectDelivered {
+    peer_id: String,
+    dm_id: u64,
+  },
+  DirectFailed {
+    peer_id: String,
+    dm_id: u64,
+    error: String,
+  },
+}
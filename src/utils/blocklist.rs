@@ -0,0 +1,101 @@
+use libp2p::PeerId;
+use std::{
+  collections::BTreeSet,
+  error::Error,
+  fs,
+  path::{Path, PathBuf},
+};
+
+/// Blocked peers, saved one PeerId per line after each change. A change that cannot be saved
+/// is undone.
+#[derive(Debug, Default)]
+pub struct Blocklist {
+  /// Kept in memory only when unset.
+  path: Option<PathBuf>,
+  peers: BTreeSet<PeerId>,
+}
+
+impl Blocklist {
+  /// Load the list at `path`, empty if the file does not exist yet.
+  pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+    let peers = match fs::read_to_string(path) {
+      Ok(content) => content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+          line
+            .parse()
+            .map_err(|er| format!("Invalid peer {line} in {}: {er}", path.display()))
+        })
+        .collect::<Result<_, _>>()?,
+      Err(er) if er.kind() == std::io::ErrorKind::NotFound => BTreeSet::new(),
+      Err(er) => return Err(format!("Cannot read blocklist {}: {er}", path.display()).into()),
+    };
+    Ok(Blocklist {
+      path: Some(path.to_path_buf()),
+      peers,
+    })
+  }
+
+  pub fn contains(&self, peer_id: &PeerId) -> bool {
+    self.peers.contains(peer_id)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &PeerId> {
+    self.peers.iter()
+  }
+
+  pub fn len(&self) -> usize {
+    self.peers.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.peers.is_empty()
+  }
+
+  /// Block a peer. Returns `false` if it already was.
+  pub fn insert(&mut self, peer_id: PeerId) -> Result<bool, Box<dyn Error>> {
+    if !self.peers.insert(peer_id) {
+      return Ok(false);
+    }
+    if let Err(er) = self.save() {
+      self.peers.remove(&peer_id);
+      return Err(er);
+    }
+    Ok(true)
+  }
+
+  /// Unblock a peer. Returns `false` if it was not blocked.
+  pub fn remove(&mut self, peer_id: &PeerId) -> Result<bool, Box<dyn Error>> {
+    if !self.peers.remove(peer_id) {
+      return Ok(false);
+    }
+    if let Err(er) = self.save() {
+      self.peers.insert(*peer_id);
+      return Err(er);
+    }
+    Ok(true)
+  }
+
+  /// Replace the file at once, so that it is never left half written.
+  fn save(&self) -> Result<(), Box<dyn Error>> {
+    let Some(path) = &self.path else {
+      return Ok(());
+    };
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+      fs::create_dir_all(dir)?;
+    }
+    let content = self
+      .peers
+      .iter()
+      .map(|peer_id| format!("{peer_id}\n"))
+      .collect::<String>();
+    let mut tmp = path.clone().into_os_string();
+    tmp.push(".tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, path)
+      .map_err(|er| format!("Cannot save blocklist {}: {er}", path.display()))?;
+    Ok(())
+  }
+}
@@ -0,0 +1,104 @@
+/// A line typed by the user.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+  /// Subscribe to a room and make it the current one.
+  Join(String),
+  /// Unsubscribe from a room. Defaults to the current room.
+  Leave(Option<String>),
+  /// Make an already joined room the current one.
+  Switch(String),
+  /// List joined rooms.
+  Rooms,
+  /// Change our nickname.
+  Nick(String),
+  /// Send a direct message to a peer, by PeerId or nickname.
+  Msg {
+    peer: String,
+    text: String,
+  },
+  /// Create a private room and make it the current one.
+  Private(String),
+  /// Send the key of a private room to a peer. Defaults to the current room.
+  Invite {
+    peer: String,
+    room: Option<String>,
+  },
+  /// Remove a peer from a private room and rotate its key. Defaults to the current room.
+  Kick {
+    peer: String,
+    room: Option<String>,
+  },
+  /// Forget the invitations to a private room, only the one of a peer if given.
+  Decline {
+    room: String,
+    peer: Option<String>,
+  },
+  /// Refuse the connections and messages of a peer, by PeerId or nickname.
+  Block(String),
+  Unblock(String),
+  /// List the blocked peers.
+  Blocked,
+  /// Replay the last messages of the current room.
+  History(Option<usize>),
+  /// Plain text sent to the current room.
+  Say(String),
+}
+
+impl Command {
+  pub fn parse(line: &str) -> Result<Self, String> {
+    let Some(line) = line.strip_prefix('/') else {
+      return Ok(Command::Say(line.to_string()));
+    };
+    let (cmd, rest) = split_word(line);
+    let (arg, text) = split_word(rest);
+    let arg = (!arg.is_empty()).then(|| arg.to_string());
+    match (cmd, arg) {
+      ("join", Some(room)) => Ok(Command::Join(room)),
+      ("leave", room) => Ok(Command::Leave(room)),
+      ("switch", Some(room)) => Ok(Command::Switch(room)),
+      ("rooms", None) => Ok(Command::Rooms),
+      ("nick", Some(nick)) => Ok(Command::Nick(nick)),
+      ("msg", Some(peer)) if !text.is_empty() => Ok(Command::Msg {
+        peer,
+        text: text.to_string(),
+      }),
+      ("join" | "switch", None) => Err(format!("Usage: /{cmd} <room>")),
+      ("nick", None) => Err("Usage: /nick <nickname>".to_string()),
+      ("private", Some(room)) => Ok(Command::Private(room)),
+      ("invite", Some(peer)) => Ok(Command::Invite {
+        peer,
+        room: (!text.is_empty()).then(|| text.to_string()),
+      }),
+      ("kick", Some(peer)) => Ok(Command::Kick {
+        peer,
+        room: (!text.is_empty()).then(|| text.to_string()),
+      }),
+      ("decline", Some(room)) => Ok(Command::Decline {
+        room,
+        peer: (!text.is_empty()).then(|| text.to_string()),
+      }),
+      ("block", Some(peer)) => Ok(Command::Block(peer)),
+      ("unblock", Some(peer)) => Ok(Command::Unblock(peer)),
+      ("blocked", None) => Ok(Command::Blocked),
+      ("history", None) => Ok(Command::History(None)),
+      ("history", Some(n)) => match n.parse() {
+        Ok(n) => Ok(Command::History(Some(n))),
+        Err(_) => Err("Usage: /history [n]".to_string()),
+      },
+      ("private", None) => Err("Usage: /private <room>".to_string()),
+      ("invite" | "kick", None) => Err(format!("Usage: /{cmd} <peer|nick> [room]")),
+      ("decline", None) => Err("Usage: /decline <room> [peer|nick]".to_string()),
+      ("block" | "unblock", None) => Err(format!("Usage: /{cmd} <peer|nick>")),
+      ("msg", _) => Err("Usage: /msg <peer|nick> <text>".to_string()),
+      _ => Err(format!("Unknown command: /{line}")),
+    }
+  }
+}
+
+/// Split the first word from the rest of the line.
+fn split_word(line: &str) -> (&str, &str) {
+  let line = line.trim();
+  line
+    .split_once(char::is_whitespace)
+    .map_or((line, ""), |(word, rest)| (word, rest.trim_start()))
+}
@@ -0,0 +1,102 @@
+use chacha20poly1305::{
+  aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+  XChaCha20Poly1305, XNonce,
+};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use libp2p::{
+  identity::{Keypair, PublicKey},
+  PeerId,
+};
+use serde::{Deserialize, Serialize};
+use sha2::Sha512;
+use sha3::{Digest, Sha3_256};
+use std::error::Error;
+use x25519_dalek::StaticSecret;
+
+/// An authenticated ciphertext and its random nonce.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Sealed {
+  pub nonce: Vec<u8>,
+  pub ciphertext: Vec<u8>,
+}
+
+/// Encrypt with a symmetric key. `aad` is authenticated but not encrypted.
+pub fn seal(key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> Result<Sealed, Box<dyn Error>> {
+  let cipher = XChaCha20Poly1305::new(key.into());
+  let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+  let ciphertext = cipher
+    .encrypt(
+      &nonce,
+      Payload {
+        msg: plaintext,
+        aad,
+      },
+    )
+    .map_err(|_| "Failed to encrypt.")?;
+  Ok(Sealed {
+    nonce: nonce.to_vec(),
+    ciphertext,
+  })
+}
+
+/// Decrypt a [`Sealed`] payload, failing if it was tampered with or the key is wrong.
+pub fn open(key: &[u8; 32], sealed: &Sealed, aad: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+  if sealed.nonce.len() != 24 {
+    return Err("Invalid nonce.".into());
+  }
+  let cipher = XChaCha20Poly1305::new(key.into());
+  let payload = Payload {
+    msg: &sealed.ciphertext,
+    aad,
+  };
+  let plaintext = cipher
+    .decrypt(XNonce::from_slice(&sealed.nonce), payload)
+    .map_err(|_| "Failed to decrypt.")?;
+  Ok(plaintext)
+}
+
+/// X25519 secret derived from our ed25519 identity, the same way as RFC 8032 derives its scalar.
+fn x25519_secret(keypair: &Keypair) -> Result<StaticSecret, Box<dyn Error>> {
+  let keypair = keypair
+    .clone()
+    .try_into_ed25519()
+    .map_err(|_| "Encryption requires an ed25519 identity.")?;
+  let hash = Sha512::digest(keypair.secret().as_ref());
+  let mut scalar = [0u8; 32];
+  scalar.copy_from_slice(&hash[..32]);
+  Ok(StaticSecret::from(scalar))
+}
+
+/// X25519 public key of a peer, recovered from the ed25519 key embedded in its PeerId.
+fn x25519_public(peer_id: &PeerId) -> Result<x25519_dalek::PublicKey, Box<dyn Error>> {
+  let multihash = peer_id.as_ref();
+  if multihash.code() != 0 {
+    return Err(format!("{peer_id} does not embed its public key.").into());
+  }
+  let public_key = PublicKey::try_decode_protobuf(multihash.digest())?
+    .try_into_ed25519()
+    .map_err(|_| format!("{peer_id} is not an ed25519 identity."))?;
+  let point = CompressedEdwardsY(public_key.to_bytes())
+    .decompress()
+    .ok_or("Invalid ed25519 public key.")?;
+  Ok(x25519_dalek::PublicKey::from(
+    point.to_montgomery().to_bytes(),
+  ))
+}
+
+/// Symmetric key shared by our identity and `peer_id`. Both sides derive the same key, and
+/// nobody else can, so a payload that opens with it was sealed by the expected peer.
+pub fn shared_key(keypair: &Keypair, peer_id: &PeerId) -> Result<[u8; 32], Box<dyn Error>> {
+  let shared = x25519_secret(keypair)?.diffie_hellman(&x25519_public(peer_id)?);
+  let mut hasher = Sha3_256::new();
+  hasher.update(b"desnet-dm-key:");
+  hasher.update(shared.as_bytes());
+  Ok(hasher.finalize().into())
+}
+
+/// Binds a direct message to its sender and recipient.
+pub fn direct_aad(sender: &PeerId, recipient: &PeerId) -> Vec<u8> {
+  let mut aad = sender.to_bytes();
+  aad.extend(recipient.to_bytes());
+  aad
+}
@@ -0,0 +1,45 @@
+use libp2p::{identity::Keypair, PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+use super::{
+  crypto::{direct_aad, open, seal, shared_key, Sealed},
+  msg::Envelope,
+};
+
+pub const DM_PROTOCOL: StreamProtocol = StreamProtocol::new("/desnet/dm/1.0.0");
+
+/// A direct message sent to a single peer, end-to-end encrypted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DirectRequest {
+  pub sealed: Sealed,
+}
+
+impl DirectRequest {
+  /// Encrypt an envelope so that only `recipient` can read it.
+  pub fn seal(
+    keypair: &Keypair,
+    recipient: &PeerId,
+    envelope: &Envelope,
+  ) -> Result<Self, Box<dyn Error>> {
+    let key = shared_key(keypair, recipient)?;
+    let aad = direct_aad(&keypair.public().to_peer_id(), recipient);
+    let sealed = seal(&key, &envelope.encode(), &aad)?;
+    Ok(DirectRequest { sealed })
+  }
+
+  /// Decrypt a request sent by `sender`, which must be the authenticated remote peer.
+  pub fn open(&self, keypair: &Keypair, sender: &PeerId) -> Result<Envelope, Box<dyn Error>> {
+    let key = shared_key(keypair, sender)?;
+    let aad = direct_aad(sender, &keypair.public().to_peer_id());
+    Envelope::decode(&open(&key, &self.sealed, &aad)?)
+  }
+}
+
+/// Acknowledgement of a [`DirectRequest`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DirectResponse {
+  Delivered,
+  Rejected(String),
+}
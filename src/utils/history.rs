@@ -0,0 +1,199 @@
+use redb::{Database, ReadableTable, ReadableTableMetadata, TableDefinition, WriteTransaction};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fs, ops::Bound, path::Path, time::Duration};
+
+use super::msg::{now, Envelope, SignedMessage};
+
+/// Messages by room, reception time and insertion order, serde_json encoded [`HistoryRecord`]s.
+const MESSAGES: TableDefinition<(&str, u64, u64), &[u8]> = TableDefinition::new("messages");
+/// Key of each message in [`MESSAGES`], by message id.
+const IDS: TableDefinition<&str, (&str, u64, u64)> = TableDefinition::new("ids");
+/// Number of stored messages per room.
+const COUNTS: TableDefinition<&str, u64> = TableDefinition::new("counts");
+const META: TableDefinition<&str, u64> = TableDefinition::new("meta");
+const NEXT_SEQ: &str = "next_seq";
+
+/// Limits applied to each room, the oldest messages are dropped first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+  pub max_messages: Option<u64>,
+  pub max_age: Option<Duration>,
+}
+
+/// A chat message, sent or received, as stored on disk.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HistoryRecord {
+  pub room: String,
+  pub message_id: String,
+  /// PeerId of the author.
+  pub author: String,
+  /// Unix timestamp in milliseconds, by our clock or by the one of the peer it was synced from.
+  pub received_at: u64,
+  /// Opened when it was sealed for a private room we are a member of.
+  pub envelope: Envelope,
+  /// The message as signed by its author. Only signed messages are synced to other peers.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub signed: Option<SignedMessage>,
+}
+
+impl HistoryRecord {
+  pub fn new(room: &str, message_id: &str, author: &str, envelope: Envelope) -> Self {
+    HistoryRecord {
+      room: room.to_string(),
+      message_id: message_id.to_string(),
+      author: author.to_string(),
+      received_at: now(),
+      envelope,
+      signed: None,
+    }
+  }
+}
+
+/// Chat history in an embedded database.
+pub struct History {
+  db: Database,
+  retention: Retention,
+}
+
+impl History {
+  /// Open or create the database at `path`, and apply the retention limits to it.
+  pub fn open(path: &Path, retention: Retention) -> Result<Self, Box<dyn Error>> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+      fs::create_dir_all(dir)?;
+    }
+    let db = Database::create(path)
+      .map_err(|er| format!("Cannot open history {}: {er}", path.display()))?;
+    // It holds the opened messages of the private rooms
+    #[cfg(unix)]
+    fs::set_permissions(path, std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    let history = History { db, retention };
+    let txn = history.db.begin_write()?;
+    // Create the tables, so that they can be read before the first message
+    txn.open_table(MESSAGES)?;
+    txn.open_table(IDS)?;
+    txn.open_table(META)?;
+    let rooms = txn
+      .open_table(COUNTS)?
+      .iter()?
+      .map(|entry| Ok(entry?.0.value().to_string()))
+      .collect::<Result<Vec<String>, redb::StorageError>>()?;
+    for room in rooms {
+      history.prune(&txn, &room)?;
+    }
+    txn.commit()?;
+    Ok(history)
+  }
+
+  /// Store a message. Returns `false` if it was already stored.
+  pub fn insert(&self, record: &HistoryRecord) -> Result<bool, Box<dyn Error>> {
+    let txn = self.db.begin_write()?;
+    {
+      let mut ids = txn.open_table(IDS)?;
+      if ids.get(record.message_id.as_str())?.is_some() {
+        return Ok(false);
+      }
+      let mut meta = txn.open_table(META)?;
+      let seq = meta.get(NEXT_SEQ)?.map(|v| v.value()).unwrap_or(0);
+      meta.insert(NEXT_SEQ, seq + 1)?;
+      let key = (record.room.as_str(), record.received_at, seq);
+      ids.insert(record.message_id.as_str(), key)?;
+      let mut messages = txn.open_table(MESSAGES)?;
+      messages.insert(key, serde_json::to_vec(record)?.as_slice())?;
+      let room = record.room.as_str();
+      let mut counts = txn.open_table(COUNTS)?;
+      let count = counts.get(room)?.map(|v| v.value()).unwrap_or(0);
+      counts.insert(room, count + 1)?;
+    }
+    self.prune(&txn, &record.room)?;
+    txn.commit()?;
+    Ok(true)
+  }
+
+  pub fn contains(&self, message_id: &str) -> Result<bool, Box<dyn Error>> {
+    let txn = self.db.begin_read()?;
+    let ids = txn.open_table(IDS)?;
+    Ok(ids.get(message_id)?.is_some())
+  }
+
+  /// The last `limit` messages of a room, oldest first.
+  pub fn last(&self, room: &str, limit: usize) -> Result<Vec<HistoryRecord>, Box<dyn Error>> {
+    self.after(room, None, None, limit)
+  }
+
+  /// The last `limit` messages of a room received after the message `after`, or when we do not
+  /// have it, since the timestamp `since`. Oldest first.
+  pub fn after(
+    &self,
+    room: &str,
+    after: Option<&str>,
+    since: Option<u64>,
+    limit: usize,
+  ) -> Result<Vec<HistoryRecord>, Box<dyn Error>> {
+    let txn = self.db.begin_read()?;
+    let ids = txn.open_table(IDS)?;
+    let mut start = Bound::Included((room, since.unwrap_or(0), 0));
+    if let Some(after) = after {
+      if let Some(key) = ids.get(after)? {
+        let (stored_room, received_at, seq) = key.value();
+        if stored_room == room {
+          start = Bound::Excluded((room, received_at, seq));
+        }
+      }
+    }
+    let end = Bound::Included((room, u64::MAX, u64::MAX));
+    let messages = txn.open_table(MESSAGES)?;
+    let mut records = messages
+      .range::<(&str, u64, u64)>((start, end))?
+      .rev()
+      .take(limit)
+      .map(|entry| Ok(serde_json::from_slice(entry?.1.value())?))
+      .collect::<Result<Vec<HistoryRecord>, Box<dyn Error>>>()?;
+    records.reverse();
+    Ok(records)
+  }
+
+  /// Number of stored messages, all rooms included.
+  pub fn len(&self) -> Result<u64, Box<dyn Error>> {
+    let txn = self.db.begin_read()?;
+    Ok(txn.open_table(MESSAGES)?.len()?)
+  }
+
+  pub fn is_empty(&self) -> Result<bool, Box<dyn Error>> {
+    Ok(self.len()? == 0)
+  }
+
+  /// Drop the oldest messages of a room beyond the retention limits.
+  fn prune(&self, txn: &WriteTransaction, room: &str) -> Result<(), Box<dyn Error>> {
+    let mut messages = txn.open_table(MESSAGES)?;
+    let mut counts = txn.open_table(COUNTS)?;
+    let mut count = counts.get(room)?.map(|v| v.value()).unwrap_or(0);
+    let cutoff = self
+      .retention
+      .max_age
+      .map(|age| now().saturating_sub(age.as_millis() as u64));
+    let mut expired = Vec::new();
+    for entry in messages.range((room, 0, 0)..=(room, u64::MAX, u64::MAX))? {
+      let (key, value) = entry?;
+      let record: HistoryRecord = serde_json::from_slice(value.value())?;
+      let too_many = self.retention.max_messages.is_some_and(|max| count > max);
+      let too_old = cutoff.is_some_and(|cutoff| record.received_at < cutoff);
+      if !too_many && !too_old {
+        break;
+      }
+      let (_, received_at, seq) = key.value();
+      expired.push((received_at, seq, record.message_id));
+      count -= 1;
+    }
+    let mut ids = txn.open_table(IDS)?;
+    for (received_at, seq, message_id) in expired {
+      messages.remove((room, received_at, seq))?;
+      ids.remove(message_id.as_str())?;
+    }
+    if count == 0 {
+      counts.remove(room)?;
+    } else {
+      counts.insert(room, count)?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,189 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use libp2p::identity::Keypair;
+use serde::{Deserialize, Serialize};
+use std::{
+  error::Error,
+  fs,
+  io::Write,
+  path::{Path, PathBuf},
+};
+
+use super::crypto::{open, seal, Sealed};
+
+/// Environment variable holding the keystore passphrase, kept out of the shell history.
+pub const PASSPHRASE_ENV: &str = "DESNET_PASSPHRASE";
+
+/// `~/.desnet/identity.key`, or `identity.key` in the working directory without a home.
+pub fn default_path() -> PathBuf {
+  match std::env::var_os("HOME") {
+    Some(home) => Path::new(&home).join(".desnet").join("identity.key"),
+    None => PathBuf::from("identity.key"),
+  }
+}
+
+/// On-disk format of the keystore. The keypair is protobuf encoded.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Keystore {
+  Plain {
+    keypair: Vec<u8>,
+  },
+  /// Sealed with a key derived from the passphrase by argon2id.
+  Argon2id {
+    salt: Vec<u8>,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    sealed: Sealed,
+  },
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], params: Params) -> Result<[u8; 32], Box<dyn Error>> {
+  let mut key = [0u8; 32];
+  Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+    .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+    .map_err(|er| format!("Failed to derive the keystore key: {er}"))?;
+  Ok(key)
+}
+
+/// Read a keypair from the keystore at `path`.
+pub fn load(path: &Path, passphrase: Option<&str>) -> Result<Keypair, Box<dyn Error>> {
+  check_permissions(path)?;
+  let keystore: Keystore = serde_json::from_slice(&fs::read(path)?)
+    .map_err(|er| format!("Invalid keystore {}: {er}", path.display()))?;
+  let bytes = match keystore {
+    Keystore::Plain { keypair } => keypair,
+    Keystore::Argon2id {
+      salt,
+      m_cost,
+      t_cost,
+      p_cost,
+      sealed,
+    } => {
+      let passphrase = passphrase.ok_or_else(|| {
+        format!(
+          "Keystore {} is encrypted, set {PASSPHRASE_ENV}.",
+          path.display()
+        )
+      })?;
+      let params = Params::new(m_cost, t_cost, p_cost, None)
+        .map_err(|er| format!("Invalid keystore parameters: {er}"))?;
+      let key = derive_key(passphrase, &salt, params)?;
+      open(&key, &sealed, b"desnet-keystore").map_err(|_| "Wrong keystore passphrase.")?
+    }
+  };
+  Ok(Keypair::from_protobuf_encoding(&bytes)?)
+}
+
+/// Write a keypair to the keystore at `path`, encrypted when a passphrase is given.
+pub fn save(
+  path: &Path,
+  keypair: &Keypair,
+  passphrase: Option<&str>,
+  overwrite: bool,
+) -> Result<(), Box<dyn Error>> {
+  if !overwrite && path.exists() {
+    return Err(
+      format!(
+        "{} already exists, use --force to replace it.",
+        path.display()
+      )
+      .into(),
+    );
+  }
+  let bytes = keypair.to_protobuf_encoding()?;
+  let keystore = match passphrase {
+    None => Keystore::Plain { keypair: bytes },
+    Some(passphrase) => {
+      let mut salt = vec![0u8; 16];
+      OsRng.fill_bytes(&mut salt);
+      let params = Params::default();
+      let key = derive_key(passphrase, &salt, params.clone())?;
+      Keystore::Argon2id {
+        salt,
+        m_cost: params.m_cost(),
+        t_cost: params.t_cost(),
+        p_cost: params.p_cost(),
+        sealed: seal(&key, &bytes, b"desnet-keystore")?,
+      }
+    }
+  };
+  write_private(path, &serde_json::to_vec(&keystore)?, overwrite)
+}
+
+/// Load the keystore, creating it with a new ed25519 keypair on first run.
+/// Returns whether it has been created.
+pub fn load_or_create(
+  path: &Path,
+  passphrase: Option<&str>,
+) -> Result<(Keypair, bool), Box<dyn Error>> {
+  if path.exists() {
+    return Ok((load(path, passphrase)?, false));
+  }
+  let keypair = Keypair::generate_ed25519();
+  save(path, &keypair, passphrase, false)?;
+  Ok((keypair, true))
+}
+
+/// Write the protobuf encoded keypair, unencrypted, for `import` on another machine.
+pub fn export(path: &Path, keypair: &Keypair) -> Result<(), Box<dyn Error>> {
+  write_private(path, &keypair.to_protobuf_encoding()?, false)
+}
+
+/// Read a protobuf encoded keypair written by [`export`].
+pub fn read_exported(path: &Path) -> Result<Keypair, Box<dyn Error>> {
+  check_permissions(path)?;
+  Ok(Keypair::from_protobuf_encoding(&fs::read(path)?)?)
+}
+
+/// Create a file only readable by its owner.
+fn write_private(path: &Path, data: &[u8], overwrite: bool) -> Result<(), Box<dyn Error>> {
+  if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)?;
+  }
+  let mut options = fs::OpenOptions::new();
+  options.write(true);
+  if overwrite {
+    options.create(true).truncate(true);
+  } else {
+    options.create_new(true);
+  }
+  #[cfg(unix)]
+  std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+  let mut file = options
+    .open(path)
+    .map_err(|er| format!("Cannot write {}: {er}", path.display()))?;
+  // The mode only applies to new files
+  #[cfg(unix)]
+  file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+  file.write_all(data)?;
+  Ok(())
+}
+
+/// Refuse key files that other users can read or write.
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<(), Box<dyn Error>> {
+  use std::os::unix::fs::PermissionsExt;
+  let mode = fs::metadata(path)?.permissions().mode();
+  if mode & 0o077 != 0 {
+    return Err(
+      format!(
+        "{} is accessible by other users (mode {:o}), run `chmod 600` on it.",
+        path.display(),
+        mode & 0o777
+      )
+      .into(),
+    );
+  }
+  Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Result<(), Box<dyn Error>> {
+  Ok(())
+}
@@ -1,11 +1,193 @@
-use libp2p::gossipsub::{Message, MessageId};
+use libp2p::{
+  gossipsub::{Message, MessageId, TopicHash},
+  identity::{Keypair, PublicKey},
+  PeerId,
+};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
 use std::{
-  collections::hash_map::DefaultHasher,
-  hash::{Hash, Hasher},
+  error::Error,
+  time::{SystemTime, UNIX_EPOCH},
 };
 
+/// Identify a message by its author, sequence number and content.
+///
+/// Identical texts from different peers, or sent twice by the same peer, get distinct ids.
 pub fn message_id(message: &Message) -> MessageId {
-  let mut s = DefaultHasher::new();
-  message.data.hash(&mut s);
-  MessageId::from(s.finish().to_string())
+  let source = message
+    .source
+    .map(|peer_id| peer_id.to_bytes())
+    .unwrap_or_default();
+  let seqno = message.sequence_number.unwrap_or_default();
+  let mut hasher = Sha3_256::new();
+  hasher.update((source.len() as u64).to_be_bytes());
+  hasher.update(&source);
+  hasher.update(seqno.to_be_bytes());
+  hasher.update(&message.data);
+  MessageId::from(bs58::encode(hasher.finalize()).into_string())
+}
+
+/// Prefix of the bytes signed by the authors of gossipsub messages.
+const SIGNING_PREFIX: &[u8] = b"libp2p-pubsub:";
+
+/// A message of a room as signed by its author, kept so that the peers it is synced to can check
+/// it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SignedMessage {
+  pub seqno: u64,
+  /// The payload as published, sealed in private rooms.
+  pub data: Vec<u8>,
+  pub signature: Vec<u8>,
+  /// Public key of the author, when it is not inlined in its PeerId.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub key: Option<Vec<u8>>,
+}
+
+impl SignedMessage {
+  /// Sign a message the way gossipsub does when publishing it.
+  pub fn sign(
+    keypair: &Keypair,
+    room: &str,
+    seqno: u64,
+    data: Vec<u8>,
+  ) -> Result<Self, Box<dyn Error>> {
+    let author = keypair.public().to_peer_id();
+    let signature = keypair.sign(&signed_bytes(&author, room, seqno, &data))?;
+    let key = inlined_key(&author)
+      .is_none()
+      .then(|| keypair.public().encode_protobuf());
+    Ok(SignedMessage {
+      seqno,
+      data,
+      signature,
+      key,
+    })
+  }
+
+  /// The gossipsub message, if it was signed by `author` for `room`.
+  pub fn verify(&self, author: &PeerId, room: &str) -> Result<Message, Box<dyn Error>> {
+    let key = match &self.key {
+      Some(key) => PublicKey::try_decode_protobuf(key)?,
+      None => inlined_key(author).ok_or("No public key for the author.")?,
+    };
+    if key.to_peer_id() != *author {
+      return Err("Signed by another peer.".into());
+    }
+    let signed = signed_bytes(author, room, self.seqno, &self.data);
+    if !key.verify(&signed, &self.signature) {
+      return Err("Invalid signature.".into());
+    }
+    Ok(Message {
+      source: Some(*author),
+      data: self.data.clone(),
+      sequence_number: Some(self.seqno),
+      topic: TopicHash::from_raw(room),
+    })
+  }
+}
+
+/// The public key of an ed25519 PeerId, which is inlined in it.
+fn inlined_key(peer_id: &PeerId) -> Option<PublicKey> {
+  PublicKey::try_decode_protobuf(peer_id.to_bytes().get(2..)?).ok()
+}
+
+/// The gossipsub protobuf of a message without its signature, as signed by its author.
+fn signed_bytes(author: &PeerId, room: &str, seqno: u64, data: &[u8]) -> Vec<u8> {
+  let from = author.to_bytes();
+  let seqno = seqno.to_be_bytes();
+  // Tags of the `from`, `data`, `seqno` and `topic` fields
+  let fields: [(u8, &[u8]); 4] = [
+    (0x0a, &from),
+    (0x12, data),
+    (0x1a, &seqno),
+    (0x22, room.as_bytes()),
+  ];
+  let mut bytes = SIGNING_PREFIX.to_vec();
+  for (tag, field) in fields {
+    bytes.push(tag);
+    let mut len = field.len();
+    while len >= 0x80 {
+      bytes.push(len as u8 | 0x80);
+      len >>= 7;
+    }
+    bytes.push(len as u8);
+    bytes.extend_from_slice(field);
+  }
+  bytes
+}
+
+/// Current envelope version.
+pub const VERSION: u8 = 1;
+
+pub const TEXT_PLAIN: &str = "text/plain";
+pub const APPLICATION_JSON: &str = "application/json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+  Text,
+  /// A message of a private room, whose body is sealed with the room key.
+  Encrypted,
+  /// A private room key, only sent over direct messages.
+  RoomKey,
+  /// A kind introduced by a newer version. It is relayed but not displayed.
+  #[serde(other)]
+  Unknown,
+}
+
+/// What travels in a gossipsub message payload.
+///
+/// Encoded as JSON so that newer peers can add fields without breaking older ones.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+  pub version: u8,
+  pub kind: Kind,
+  /// Nickname of the author, if any.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub sender: Option<String>,
+  /// Unix timestamp in milliseconds, as claimed by the author.
+  pub timestamp: u64,
+  pub body: String,
+  /// Id of the message this one replies to.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub reply_to: Option<String>,
+  pub content_type: String,
+}
+
+impl Envelope {
+  pub fn new(kind: Kind, sender: Option<String>, body: String, content_type: &str) -> Self {
+    Envelope {
+      version: VERSION,
+      kind,
+      sender,
+      timestamp: now(),
+      body,
+      reply_to: None,
+      content_type: content_type.to_string(),
+    }
+  }
+
+  pub fn text(sender: Option<String>, body: impl Into<String>) -> Self {
+    Envelope::new(Kind::Text, sender, body.into(), TEXT_PLAIN)
+  }
+
+  pub fn encode(&self) -> Vec<u8> {
+    serde_json::to_vec(self).expect("Envelope is always serializable")
+  }
+
+  pub fn decode(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+    let envelope: Envelope = serde_json::from_slice(data)?;
+    if envelope.version == 0 {
+      return Err("Invalid envelope version.".into());
+    }
+    Ok(envelope)
+  }
+}
+
+/// Current unix timestamp in milliseconds.
+pub fn now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_millis() as u64)
+    .unwrap_or_default()
 }
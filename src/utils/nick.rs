@@ -0,0 +1,85 @@
+use libp2p::{
+  identity::{Keypair, PublicKey},
+  kad::RecordKey,
+  PeerId,
+};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+use super::msg::now;
+
+/// Gossipsub topic on which nickname records are broadcast.
+pub const PRESENCE_TOPIC: &str = "desnet-presence";
+
+pub const MAX_NICKNAME_LEN: usize = 32;
+
+pub fn validate_nickname(nickname: &str) -> Result<(), Box<dyn Error>> {
+  if nickname.is_empty() || nickname.chars().count() > MAX_NICKNAME_LEN {
+    return Err(format!("Nickname must have 1 to {MAX_NICKNAME_LEN} characters.").into());
+  }
+  if nickname
+    .chars()
+    .any(|c| c.is_whitespace() || c.is_control())
+  {
+    return Err("Nickname must not contain spaces.".into());
+  }
+  Ok(())
+}
+
+/// Kademlia key under which the nickname of `peer_id` is stored.
+pub fn nickname_key(peer_id: &PeerId) -> RecordKey {
+  let mut key = b"/nick/".to_vec();
+  key.extend(peer_id.to_bytes());
+  RecordKey::new(&key)
+}
+
+/// A nickname signed by the identity key of its owner.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NickRecord {
+  /// Protobuf encoded public key of the owner.
+  pub public_key: Vec<u8>,
+  pub nickname: String,
+  /// Unix timestamp in milliseconds. Newer records replace older ones.
+  pub timestamp: u64,
+  pub signature: Vec<u8>,
+}
+
+impl NickRecord {
+  pub fn new(keypair: &Keypair, nickname: &str) -> Result<Self, Box<dyn Error>> {
+    validate_nickname(nickname)?;
+    let public_key = keypair.public().encode_protobuf();
+    let timestamp = now();
+    let signature = keypair.sign(&signing_bytes(&public_key, nickname, timestamp))?;
+    Ok(NickRecord {
+      public_key,
+      nickname: nickname.to_string(),
+      timestamp,
+      signature,
+    })
+  }
+
+  pub fn encode(&self) -> Vec<u8> {
+    serde_json::to_vec(self).expect("NickRecord is always serializable")
+  }
+
+  /// Decode a record and check its signature. Returns the owner with the record.
+  pub fn decode(data: &[u8]) -> Result<(PeerId, Self), Box<dyn Error>> {
+    let record: NickRecord = serde_json::from_slice(data)?;
+    validate_nickname(&record.nickname)?;
+    let public_key = PublicKey::try_decode_protobuf(&record.public_key)?;
+    let msg = signing_bytes(&record.public_key, &record.nickname, record.timestamp);
+    if !public_key.verify(&msg, &record.signature) {
+      return Err("Invalid nickname signature.".into());
+    }
+    Ok((public_key.to_peer_id(), record))
+  }
+}
+
+fn signing_bytes(public_key: &[u8], nickname: &str, timestamp: u64) -> Vec<u8> {
+  let mut buf = b"desnet-nick:".to_vec();
+  buf.extend((public_key.len() as u64).to_be_bytes());
+  buf.extend(public_key);
+  buf.extend(timestamp.to_be_bytes());
+  buf.extend(nickname.as_bytes());
+  buf
+}
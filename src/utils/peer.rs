@@ -1,8 +1,10 @@
-use libp2p::{identity::Keypair, PeerId};
+use libp2p::{
+  identity::Keypair, multiaddr::Protocol, swarm::dial_opts::DialOpts, Multiaddr, PeerId,
+};
 use sha3::{Digest, Keccak256};
 use std::error::Error;
 
-pub fn parse_peer_id(addr: &String) -> Result<PeerId, Box<dyn Error>> {
+pub fn parse_peer_id(addr: &str) -> Result<PeerId, Box<dyn Error>> {
   let parts: Vec<&str> = addr.split("/p2p/").collect();
   let str = parts.last().copied().ok_or("Cannot parse peer id.")?;
   let buf = bs58::decode(str).into_vec()?;
@@ -10,7 +12,31 @@ pub fn parse_peer_id(addr: &String) -> Result<PeerId, Box<dyn Error>> {
   Ok(id)
 }
 
-pub fn ed25519_from_seed(seed: &String) -> Result<Keypair, Box<dyn Error>> {
+pub fn is_quic(addr: &Multiaddr) -> bool {
+  addr.iter().any(|p| p == Protocol::QuicV1)
+}
+
+/// Sort the QUIC addresses first. Swarms try the addresses of a peer one at a time, in order.
+pub fn quic_first(addrs: &mut [Multiaddr]) {
+  addrs.sort_by_key(|addr| !is_quic(addr));
+}
+
+/// Dial a peer over its QUIC addresses first, then over the others.
+pub fn dial_quic_first(peer_id: PeerId, mut addrs: Vec<Multiaddr>) -> DialOpts {
+  quic_first(&mut addrs);
+  DialOpts::peer_id(peer_id).addresses(addrs).build()
+}
+
+/// Listen addresses of both transports on all interfaces. QUIC uses the same port, over UDP.
+pub fn listen_addrs(port: u16, quic: bool) -> Result<Vec<Multiaddr>, Box<dyn Error>> {
+  let mut addrs = vec![format!("/ip4/0.0.0.0/tcp/{port}").parse()?];
+  if quic {
+    addrs.push(format!("/ip4/0.0.0.0/udp/{port}/quic-v1").parse()?);
+  }
+  Ok(addrs)
+}
+
+pub fn ed25519_from_seed(seed: &str) -> Result<Keypair, Box<dyn Error>> {
   let mut hasher = Keccak256::new();
   hasher.update(seed.as_bytes());
   let bytes = hasher.finalize();
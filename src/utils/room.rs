@@ -0,0 +1,86 @@
+use chacha20poly1305::{
+  aead::{KeyInit, OsRng},
+  XChaCha20Poly1305,
+};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+use super::{
+  crypto::{open, seal, Sealed},
+  msg::{Envelope, Kind, APPLICATION_JSON},
+};
+
+/// Symmetric key of a private room. A new epoch starts each time the key is rotated.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RoomKey {
+  pub room: String,
+  pub epoch: u64,
+  pub key: [u8; 32],
+}
+
+impl std::fmt::Debug for RoomKey {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("RoomKey")
+      .field("room", &self.room)
+      .field("epoch", &self.epoch)
+      .finish_non_exhaustive()
+  }
+}
+
+/// Body of an [`Kind::Encrypted`] envelope.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct RoomCiphertext {
+  epoch: u64,
+  sealed: Sealed,
+}
+
+impl RoomKey {
+  pub fn generate(room: &str, epoch: u64) -> Self {
+    RoomKey {
+      room: room.to_string(),
+      epoch,
+      key: XChaCha20Poly1305::generate_key(&mut OsRng).into(),
+    }
+  }
+
+  /// Wrap the key in an envelope, to be sent over an encrypted direct message.
+  pub fn to_envelope(&self) -> Envelope {
+    let body = serde_json::to_string(self).expect("RoomKey is always serializable");
+    Envelope::new(Kind::RoomKey, None, body, APPLICATION_JSON)
+  }
+
+  pub fn from_envelope(envelope: &Envelope) -> Result<Self, Box<dyn Error>> {
+    if envelope.kind != Kind::RoomKey {
+      return Err("Not a room key.".into());
+    }
+    Ok(serde_json::from_str(&envelope.body)?)
+  }
+
+  /// Encrypt an envelope for the room. The author nickname is only visible to members.
+  pub fn seal(&self, envelope: &Envelope) -> Result<Envelope, Box<dyn Error>> {
+    let sealed = seal(&self.key, &envelope.encode(), &self.aad(self.epoch))?;
+    let ciphertext = RoomCiphertext {
+      epoch: self.epoch,
+      sealed,
+    };
+    let body = serde_json::to_string(&ciphertext)?;
+    Ok(Envelope::new(Kind::Encrypted, None, body, APPLICATION_JSON))
+  }
+
+  /// Decrypt an envelope sealed with this key.
+  pub fn open(&self, envelope: &Envelope) -> Result<Envelope, Box<dyn Error>> {
+    let ciphertext: RoomCiphertext = serde_json::from_str(&envelope.body)?;
+    if ciphertext.epoch != self.epoch {
+      return Err("Sealed with another key.".into());
+    }
+    let plaintext = open(&self.key, &ciphertext.sealed, &self.aad(ciphertext.epoch))?;
+    Envelope::decode(&plaintext)
+  }
+
+  /// Binds a ciphertext to its room and epoch.
+  fn aad(&self, epoch: u64) -> Vec<u8> {
+    let mut aad = self.room.as_bytes().to_vec();
+    aad.extend(epoch.to_be_bytes());
+    aad
+  }
+}
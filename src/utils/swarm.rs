@@ -0,0 +1,119 @@
+use libp2p::{
+  autonat,
+  core::transport::ListenerId,
+  identify,
+  identity::Keypair,
+  kad::{self, store, Mode},
+  metrics::Registry,
+  noise, relay,
+  swarm::{behaviour::toggle::Toggle, NetworkBehaviour},
+  tcp, yamux, Multiaddr, PeerId, Swarm, SwarmBuilder,
+};
+use std::{error::Error, num::NonZeroU8, time::Duration};
+
+use super::{
+  peer::{self, listen_addrs},
+  ws::{self, WsListener},
+};
+
+/// Build a swarm over TCP, QUIC, WebSocket and relay circuits, as used by chat peers and servers
+/// alike. `behaviour` is given the identity and the relay client to build the rest.
+pub fn build<B, F>(
+  keypair: Keypair,
+  websocket: Option<&WsListener>,
+  registry: &mut Registry,
+  idle_timeout: Duration,
+  behaviour: F,
+) -> Result<Swarm<B>, Box<dyn Error>>
+where
+  B: NetworkBehaviour,
+  F: FnOnce(&Keypair, relay::client::Behaviour) -> Result<B, Box<dyn Error + Send + Sync>>,
+{
+  let ws_tls = websocket.and_then(|listener| listener.tls.clone());
+  let swarm = SwarmBuilder::with_existing_identity(keypair)
+    .with_tokio()
+    .with_tcp(
+      tcp::Config::default(),
+      noise::Config::new,
+      yamux::Config::default,
+    )?
+    .with_quic()
+    .with_other_transport(|key| ws::transport(key, ws_tls))?
+    .with_dns()?
+    .with_relay_client(noise::Config::new, yamux::Config::default)?
+    .with_bandwidth_metrics(registry)
+    .with_behaviour(behaviour)?
+    .with_swarm_config(|c| {
+      // One address at a time, so that QUIC is tried before TCP, see `peer::quic_first`
+      c.with_idle_connection_timeout(idle_timeout)
+        .with_dial_concurrency_factor(NonZeroU8::MIN)
+    })
+    .build();
+  Ok(swarm)
+}
+
+/// Without a cache of the identified addresses: Kademlia keeps them, QUIC first, while the
+/// cache would offer them to dials in any order.
+pub fn identify(key: &Keypair, protocol: impl Into<String>) -> identify::Behaviour {
+  identify::Behaviour::new(identify::Config::new(protocol.into(), key.public()).with_cache_size(0))
+}
+
+/// Add the addresses a peer listens on to Kademlia, if it runs it, QUIC first.
+pub fn add_identified(
+  kademlia: &mut kad::Behaviour<store::MemoryStore>,
+  peer_id: PeerId,
+  info: &identify::Info,
+) {
+  if !info.protocols.contains(&kad::PROTOCOL_NAME) {
+    return;
+  }
+  let mut addrs = info.listen_addrs.clone();
+  peer::quic_first(&mut addrs);
+  for addr in addrs {
+    kademlia.add_address(&peer_id, addr);
+  }
+}
+
+/// Kademlia in server mode: every node answers the queries of the others.
+pub fn kademlia(key: &Keypair, query_timeout: Duration) -> kad::Behaviour<store::MemoryStore> {
+  let peer_id = key.public().to_peer_id();
+  let mut cfg = kad::Config::default();
+  cfg.set_query_timeout(query_timeout);
+  let mut kademlia = kad::Behaviour::with_config(peer_id, store::MemoryStore::new(peer_id), cfg);
+  kademlia.set_mode(Some(Mode::Server));
+  kademlia
+}
+
+pub fn autonat(key: &Keypair) -> autonat::Behaviour {
+  autonat::Behaviour::new(key.public().to_peer_id(), Default::default())
+}
+
+/// Relaying for the peers behind a NAT, when enabled.
+pub fn relay_server(key: &Keypair, enabled: bool) -> Toggle<relay::Behaviour> {
+  enabled
+    .then(|| relay::Behaviour::new(key.public().to_peer_id(), Default::default()))
+    .into()
+}
+
+/// Listen on all interfaces, and announce the public addresses that cannot be discovered.
+/// Returns the listeners.
+pub fn listen<B: NetworkBehaviour>(
+  swarm: &mut Swarm<B>,
+  port: u16,
+  quic: bool,
+  websocket: Option<&WsListener>,
+  external_addrs: impl IntoIterator<Item = Multiaddr>,
+) -> Result<Vec<ListenerId>, Box<dyn Error>> {
+  let mut addrs = listen_addrs(port, quic)?;
+  if let Some(listener) = websocket {
+    addrs.push(listener.listen_addr()?);
+  }
+  let listeners = addrs
+    .into_iter()
+    .map(|addr| swarm.listen_on(addr))
+    .collect::<Result<_, _>>()?;
+  for addr in external_addrs {
+    swarm.add_external_address(addr);
+  }
+  Ok(listeners)
+}
@@ -0,0 +1,33 @@
+use libp2p::StreamProtocol;
+use serde::{Deserialize, Serialize};
+
+use super::history::HistoryRecord;
+
+pub const SYNC_PROTOCOL: StreamProtocol = StreamProtocol::new("/desnet/sync/1.0.0");
+
+/// Most messages sent in response to a [`SyncRequest`].
+pub const MAX_SYNC_MESSAGES: usize = 200;
+
+/// Margin applied to `since`, as the clocks of the peers differ.
+pub const SYNC_CLOCK_SKEW_MS: u64 = 60_000;
+
+/// Ask a member of a room for the messages we missed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SyncRequest {
+  pub room: String,
+  /// Id of the last message we have.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub after: Option<String>,
+  /// Unix timestamp in milliseconds, used when the peer does not have `after`.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub since: Option<u64>,
+  pub limit: usize,
+}
+
+/// Messages of the room, oldest first. Those of a private room are sealed with its key.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncResponse {
+  Messages(Vec<HistoryRecord>),
+  Refused(String),
+}
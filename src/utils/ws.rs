@@ -0,0 +1,64 @@
+use libp2p::{
+  core::{muxing::StreamMuxerBox, transport::Boxed, upgrade},
+  identity::Keypair,
+  noise, tcp,
+  websocket::{self, tls},
+  yamux, Multiaddr, PeerId, Transport,
+};
+use std::{error::Error, fs, io::BufReader, path::Path};
+
+/// WebSocket listener for browser clients, secure when it has a certificate.
+#[derive(Clone)]
+pub struct WsListener {
+  pub port: u16,
+  pub tls: Option<tls::Config>,
+}
+
+impl WsListener {
+  /// `/ws`, or `/wss` with a certificate, on all interfaces.
+  pub fn listen_addr(&self) -> Result<Multiaddr, Box<dyn Error>> {
+    let protocol = if self.tls.is_some() { "wss" } else { "ws" };
+    Ok(format!("/ip4/0.0.0.0/tcp/{}/{protocol}", self.port).parse()?)
+  }
+}
+
+/// Read a PEM certificate chain and its private key.
+pub fn load_tls(cert: &Path, key: &Path) -> Result<tls::Config, Box<dyn Error>> {
+  let read = |path: &Path| -> Result<BufReader<fs::File>, Box<dyn Error>> {
+    let file =
+      fs::File::open(path).map_err(|er| format!("Cannot read {}: {er}", path.display()))?;
+    Ok(BufReader::new(file))
+  };
+  let certs = rustls_pemfile::certs(&mut read(cert)?)
+    .map(|cert| Ok(tls::Certificate::new(cert?.to_vec())))
+    .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+  if certs.is_empty() {
+    return Err(format!("No certificate in {}.", cert.display()).into());
+  }
+  let private_key = rustls_pemfile::private_key(&mut read(key)?)?
+    .ok_or_else(|| format!("No private key in {}.", key.display()))?;
+  let config = tls::Config::new(
+    tls::PrivateKey::new(private_key.secret_der().to_vec()),
+    certs,
+  )?;
+  Ok(config)
+}
+
+/// WebSocket over TCP, secured by noise and multiplexed by yamux like the other transports.
+/// Dials both `/ws` and `/wss` addresses.
+pub fn transport(
+  keypair: &Keypair,
+  tls: Option<tls::Config>,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>, Box<dyn Error + Send + Sync>> {
+  let mut ws = websocket::WsConfig::new(tcp::tokio::Transport::new(tcp::Config::default()));
+  if let Some(tls) = tls {
+    ws.set_tls_config(tls);
+  }
+  let transport = ws
+    .upgrade(upgrade::Version::V1Lazy)
+    .authenticate(noise::Config::new(keypair)?)
+    .multiplex(yamux::Config::default())
+    .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+    .boxed();
+  Ok(transport)
+}
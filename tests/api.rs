@@ -0,0 +1,82 @@
+use libp2p::PeerId;
+use rust_libp2p_chat::{
+  utils::{
+    api::{ApiCommand, ApiEvent, ApiRequest},
+    msg::Envelope,
+  },
+  ChatEvent, ChatNode,
+};
+use serde_json::json;
+
+fn request(line: &str) -> ApiRequest {
+  serde_json::from_str(line).unwrap()
+}
+
+#[test]
+fn commands_are_parsed_with_their_optional_id() {
+  let publish = request(r#"{"id": 7, "command": "publish", "room": "r", "text": "hi"}"#);
+  assert_eq!(publish.id, Some(json!(7)));
+  assert_eq!(
+    publish.command,
+    ApiCommand::Publish {
+      room: "r".to_string(),
+      text: "hi".to_string()
+    }
+  );
+  let dm = request(r#"{"command": "dm", "peer": "alice", "text": "hi"}"#);
+  assert_eq!(dm.id, None);
+  assert_eq!(
+    dm.command,
+    ApiCommand::Dm {
+      peer: "alice".to_string(),
+      text: "hi".to_string()
+    }
+  );
+}
+
+#[test]
+fn unknown_or_incomplete_commands_are_rejected() {
+  assert!(serde_json::from_str::<ApiRequest>(r#"{"command": "shout", "text": "hi"}"#).is_err());
+  assert!(serde_json::from_str::<ApiRequest>(r#"{"command": "join"}"#).is_err());
+}
+
+#[tokio::test]
+async fn commands_report_their_outcome() {
+  let mut node = ChatNode::builder().quic(false).build().unwrap();
+  let joined = node.execute(request(r#"{"id": "a", "command": "join", "room": "r"}"#));
+  assert_eq!(
+    serde_json::to_value(ApiEvent::Result(joined)).unwrap(),
+    json!({"event": "result", "id": "a", "ok": true})
+  );
+  assert!(node.is_joined("r"));
+
+  let left = node.execute(request(
+    r#"{"id": "b", "command": "leave", "room": "other"}"#,
+  ));
+  assert_eq!(
+    serde_json::to_value(ApiEvent::Result(left)).unwrap(),
+    json!({"event": "result", "id": "b", "ok": false, "error": "Not in other."})
+  );
+
+  let nick = node.execute(request(r#"{"command": "nick", "nickname": "with space"}"#));
+  assert!(!nick.ok);
+  assert_eq!(serde_json::to_value(&nick).unwrap()["id"], json!(null));
+
+  let rooms = node.execute(request(r#"{"command": "rooms"}"#));
+  assert_eq!(
+    serde_json::to_value(&rooms).unwrap(),
+    json!({"id": null, "ok": true, "rooms": [{"name": "r", "private": false}]})
+  );
+}
+
+#[tokio::test]
+async fn claimed_nicknames_are_told_apart() {
+  let node = ChatNode::builder().quic(false).build().unwrap();
+  let event = ChatEvent::DirectMessage {
+    peer_id: PeerId::random(),
+    envelope: Envelope::text(Some("alice".to_string()), "hi"),
+  };
+  let event = serde_json::to_value(node.api_event(&event).unwrap()).unwrap();
+  assert_eq!(event["nickname"], json!(null));
+  assert_eq!(event["claimed_nickname"], json!("alice"));
+}
@@ -0,0 +1,129 @@
+use libp2p::PeerId;
+use rust_libp2p_chat::{utils::blocklist::Blocklist, ChatEvent, ChatNode};
+use std::{path::PathBuf, time::Duration};
+use tokio::time::{interval, sleep, timeout};
+
+mod common;
+use common::{listen_addr, temp_path};
+
+const ROOM: &str = "block-test-room";
+
+fn blocklist_path(name: &str) -> PathBuf {
+  temp_path(name, "blocked")
+}
+
+fn body(event: ChatEvent) -> Option<String> {
+  match event {
+    ChatEvent::Message { envelope, .. } => Some(envelope.body),
+    _ => None,
+  }
+}
+
+#[test]
+fn blocked_peers_are_saved() {
+  let path = blocklist_path("saved");
+  let (alice, bob) = (PeerId::random(), PeerId::random());
+  let mut blocklist = Blocklist::open(&path).unwrap();
+  assert!(blocklist.is_empty());
+  assert!(blocklist.insert(alice).unwrap());
+  assert!(blocklist.insert(bob).unwrap());
+  assert!(!blocklist.insert(bob).unwrap());
+  assert!(blocklist.remove(&alice).unwrap());
+  assert!(!blocklist.remove(&alice).unwrap());
+
+  let reopened = Blocklist::open(&path).unwrap();
+  assert_eq!(reopened.iter().collect::<Vec<_>>(), [&bob]);
+  std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn blocks_that_cannot_be_saved_are_undone() {
+  let path = blocklist_path("unsaved");
+  // The file is replaced through `<path>.tmp`
+  let mut tmp = path.clone().into_os_string();
+  tmp.push(".tmp");
+  std::fs::create_dir(&tmp).unwrap();
+  let blocklist = Blocklist::open(&path).unwrap();
+  let mut node = ChatNode::builder().blocklist(blocklist).build().unwrap();
+  let peer_id = PeerId::random();
+  assert!(node.block(peer_id).is_err());
+  assert!(node.is_allowed(&peer_id));
+  assert_eq!(node.blocked().count(), 0);
+
+  std::fs::remove_dir(&tmp).unwrap();
+  assert!(node.block(peer_id).unwrap());
+  assert!(!node.is_allowed(&peer_id));
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn invalid_blocklists_are_refused() {
+  let path = blocklist_path("invalid");
+  std::fs::write(&path, "not a peer id\n").unwrap();
+  assert!(Blocklist::open(&path).is_err());
+  std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn messages_of_blocked_peers_are_dropped_when_relayed() {
+  // Alice and Carol are only connected through Bob, who relays their messages
+  let mut bob = ChatNode::builder()
+    .quic(false)
+    .rate_limit(0, Duration::from_secs(60))
+    .topic(ROOM)
+    .build()
+    .unwrap();
+  let addr = listen_addr(&mut bob).await;
+  let mut alice = ChatNode::builder().quic(false).topic(ROOM).build().unwrap();
+  let mut blocklist = Blocklist::default();
+  blocklist.insert(alice.local_peer_id()).unwrap();
+  let mut carol = ChatNode::builder()
+    .quic(false)
+    .blocklist(blocklist)
+    .topic(ROOM)
+    .build()
+    .unwrap();
+  assert!(!carol.is_allowed(&alice.local_peer_id()));
+  alice.swarm_mut().dial(addr.clone()).unwrap();
+  carol.swarm_mut().dial(addr).unwrap();
+
+  let mut from_bob = false;
+  let mut from_alice = Vec::new();
+  let mut sent = 0;
+  let mut tick = interval(Duration::from_millis(200));
+  let result = timeout(Duration::from_secs(30), async {
+    // Publishing fails until Bob knows the others joined the room
+    while !from_bob {
+      tokio::select! {
+        _ = tick.tick() => {
+          if alice.send_text(ROOM, format!("alice {sent}")).is_ok() {
+            sent += 1;
+          }
+          let _ = bob.send_text(ROOM, "bob");
+        }
+        event = carol.next_event() => match body(event) {
+          Some(body) if body == "bob" => from_bob = true,
+          Some(body) => from_alice.push(body),
+          None => {}
+        },
+        _ = alice.next_event() => {}
+        _ = bob.next_event() => {}
+      }
+    }
+    // Let the last ones of Alice arrive
+    let settle = sleep(Duration::from_secs(2));
+    tokio::pin!(settle);
+    loop {
+      tokio::select! {
+        _ = &mut settle => break,
+        event = carol.next_event() => from_alice.extend(body(event).filter(|body| body != "bob")),
+        _ = alice.next_event() => {}
+        _ = bob.next_event() => {}
+      }
+    }
+  })
+  .await;
+  assert!(result.is_ok(), "Carol should receive the messages of Bob");
+  assert!(sent > 0, "Alice should publish through Bob");
+  assert!(from_alice.is_empty(), "got {from_alice:?}");
+}
@@ -0,0 +1,24 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use libp2p::Multiaddr;
+use rust_libp2p_chat::{ChatEvent, ChatNode};
+use std::path::PathBuf;
+
+/// Drive the node until it listens on localhost, and return that address.
+pub async fn listen_addr(node: &mut ChatNode) -> Multiaddr {
+  loop {
+    if let ChatEvent::ListenAddr(addr) = node.next_event().await {
+      if addr.to_string().starts_with("/ip4/127.0.0.1/") {
+        return addr;
+      }
+    }
+  }
+}
+
+/// A path in the temporary directory, unique to the test process, and removed if it exists.
+pub fn temp_path(name: &str, extension: &str) -> PathBuf {
+  let path = std::env::temp_dir().join(format!("desnet-{}-{name}.{extension}", std::process::id()));
+  let _ = std::fs::remove_file(&path);
+  path
+}
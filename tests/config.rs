@@ -0,0 +1,138 @@
+use rust_libp2p_chat::config::Config;
+use std::path::{Path, PathBuf};
+
+mod common;
+use common::temp_path;
+
+fn write_config(name: &str, content: &str) -> PathBuf {
+  let path = temp_path(name, "toml");
+  std::fs::write(&path, content).unwrap();
+  path
+}
+
+/// Load without the variables of the test process.
+fn load(path: &Path, required: bool) -> Result<Config, Box<dyn std::error::Error>> {
+  Config::load_with_vars(path, required, [])
+}
+
+fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+  vars
+    .iter()
+    .map(|(name, value)| (name.to_string(), value.to_string()))
+    .collect()
+}
+
+#[test]
+fn missing_file_is_optional_unless_required() {
+  let path = temp_path("missing", "toml");
+  assert_eq!(load(&path, false).unwrap(), Config::default());
+  assert!(load(&path, true).is_err());
+}
+
+#[test]
+fn file_overrides_defaults() {
+  let path = write_config(
+    "merge",
+    "[network]\nport = 9000\n[gossipsub]\nroom = \"lobby\"\n",
+  );
+  let config = load(&path, true).unwrap();
+  assert_eq!(config.network.port, 9000);
+  assert_eq!(config.gossipsub.room, "lobby");
+  // Keys absent from the file keep their default
+  assert_eq!(
+    config.network.idle_timeout_secs,
+    Config::default().network.idle_timeout_secs
+  );
+  assert_eq!(config.kademlia, Config::default().kademlia);
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn unknown_keys_are_rejected() {
+  let path = write_config("unknown", "[network]\nprot = 9000\n");
+  assert!(load(&path, true).is_err());
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn printed_config_loads_back() {
+  let mut config = Config::default();
+  config.identity.nickname = Some("alice".to_string());
+  config.ui.silent = true;
+  let path = write_config("roundtrip", &config.to_toml());
+  assert_eq!(load(&path, true).unwrap(), config);
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn bootstrap_accepts_one_or_many() {
+  let path = write_config("one", "[network]\nbootstrap = \"/ip4/127.0.0.1/tcp/1\"\n");
+  let config = load(&path, true).unwrap();
+  assert_eq!(config.network.bootstrap, ["/ip4/127.0.0.1/tcp/1"]);
+  std::fs::remove_file(path).unwrap();
+
+  let path = write_config(
+    "many",
+    "[network]\nbootstrap = [\"/ip4/127.0.0.1/tcp/1\", \"/ip4/127.0.0.1/tcp/2\"]\n",
+  );
+  let config = load(&path, true).unwrap();
+  assert_eq!(config.network.bootstrap.len(), 2);
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn env_overrides_file() {
+  let path = write_config("env", "[network]\nport = 9000\n");
+  let vars = vars(&[
+    ("DESNET_NETWORK_PORT", "9001"),
+    ("DESNET_GOSSIPSUB_ROOM", "lobby"),
+    ("DESNET_HISTORY_PATH", "/tmp/chat.history"),
+    ("OTHER_NETWORK_PORT", "1"),
+  ]);
+  let config = Config::load_with_vars(&path, true, vars).unwrap();
+  assert_eq!(config.network.port, 9001);
+  assert_eq!(config.gossipsub.room, "lobby");
+  assert_eq!(config.history.path, Some("/tmp/chat.history".into()));
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn unknown_env_keys_are_ignored() {
+  let path = write_config("env-unknown", "");
+  let vars = vars(&[
+    ("DESNET_NETWORK_PROT", "9000"),
+    ("DESNET_NOSECTION_KEY", "1"),
+  ]);
+  let config = Config::load_with_vars(&path, true, vars).unwrap();
+  assert_eq!(config.ignored_vars, ["DESNET_NETWORK_PROT"]);
+  assert_eq!(
+    Config {
+      ignored_vars: Vec::new(),
+      ..config
+    },
+    Config::default()
+  );
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn env_numbers_and_booleans_fit_string_keys() {
+  let path = write_config("env-strings", "");
+  let numbers = vars(&[
+    ("DESNET_GOSSIPSUB_ROOM", "2024"),
+    ("DESNET_IDENTITY_NICKNAME", "1234"),
+  ]);
+  let config = Config::load_with_vars(&path, true, numbers).unwrap();
+  assert_eq!(config.gossipsub.room, "2024");
+  assert_eq!(config.identity.nickname.as_deref(), Some("1234"));
+
+  let booleans = vars(&[
+    ("DESNET_GOSSIPSUB_ROOM", "true"),
+    ("DESNET_NETWORK_MDNS", "true"),
+  ]);
+  let config = Config::load_with_vars(&path, true, booleans).unwrap();
+  assert_eq!(config.gossipsub.room, "true");
+  // Typed keys still get the parsed value
+  assert!(config.network.mdns);
+  std::fs::remove_file(path).unwrap();
+}
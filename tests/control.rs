@@ -0,0 +1,240 @@
+use futures::{SinkExt, StreamExt};
+use rust_libp2p_chat::{control, utils::api::ApiEvent, ChatNode};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::{TcpListener, TcpStream},
+  sync::{broadcast, mpsc},
+};
+use tokio_tungstenite::{
+  connect_async,
+  tungstenite::{client::IntoClientRequest, Message},
+};
+
+/// Serve the API of a node joined to `room`, driven by a spawned loop.
+async fn spawn_api(room: &str) -> (SocketAddr, broadcast::Sender<ApiEvent>) {
+  let mut node = ChatNode::builder().quic(false).topic(room).build().unwrap();
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let addr = listener.local_addr().unwrap();
+  let (calls, mut api) = mpsc::channel(16);
+  let (events, _) = broadcast::channel(control::EVENT_BUFFER);
+  tokio::spawn(control::serve(
+    listener,
+    calls,
+    events.clone(),
+    node.metrics(),
+  ));
+  let bus = events.clone();
+  tokio::spawn(async move {
+    loop {
+      tokio::select! {
+        event = node.next_event() => {
+          if let Some(event) = node.api_event(&event) {
+            let _ = bus.send(event);
+          }
+        }
+        Some((request, reply)) = api.recv() => {
+          let _ = reply.send(node.execute(request));
+        }
+      }
+    }
+  });
+  (addr, events)
+}
+
+/// Send a request, and return the status code and the JSON body of the response.
+async fn http(addr: SocketAddr, method: &str, target: &str, body: &str) -> (u16, Value) {
+  let (status, body) = http_text(addr, method, target, body).await;
+  (status, serde_json::from_str(&body).unwrap())
+}
+
+async fn http_text(addr: SocketAddr, method: &str, target: &str, body: &str) -> (u16, String) {
+  let headers = format!("Host: {addr}\r\nContent-Type: application/json");
+  raw_http(addr, method, target, &headers, body).await
+}
+
+async fn raw_http(
+  addr: SocketAddr,
+  method: &str,
+  target: &str,
+  headers: &str,
+  body: &str,
+) -> (u16, String) {
+  let mut stream = TcpStream::connect(addr).await.unwrap();
+  let request = format!(
+    "{method} {target} HTTP/1.1\r\n{headers}\r\nContent-Length: {}\r\n\r\n{body}",
+    body.len()
+  );
+  stream.write_all(request.as_bytes()).await.unwrap();
+  let mut response = String::new();
+  stream.read_to_string(&mut response).await.unwrap();
+  let (head, body) = response.split_once("\r\n\r\n").unwrap();
+  let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+  (status, body.to_string())
+}
+
+#[tokio::test]
+async fn node_and_rooms_are_listed() {
+  let (addr, _) = spawn_api("control room").await;
+  let (status, body) = http(addr, "GET", "/rooms", "").await;
+  assert_eq!(status, 200);
+  assert_eq!(
+    body,
+    json!({"id": null, "ok": true, "rooms": [{"name": "control room", "private": false}]})
+  );
+
+  let (status, body) = http(addr, "GET", "/node", "").await;
+  assert_eq!(status, 200);
+  let peer_id = body["node"]["peer_id"].as_str().unwrap();
+  let listen_addrs = body["node"]["listen_addrs"].as_array().unwrap();
+  assert!(listen_addrs
+    .iter()
+    .all(|addr| addr.as_str().unwrap().ends_with(peer_id)));
+
+  let (status, body) = http(addr, "GET", "/peers", "").await;
+  assert_eq!(
+    (status, body),
+    (200, json!({"id": null, "ok": true, "peers": []}))
+  );
+}
+
+#[tokio::test]
+async fn messages_are_posted_to_joined_rooms_only() {
+  let (addr, _) = spawn_api("control").await;
+  // Nobody else is in the room to receive it
+  let (status, body) = http(addr, "POST", "/rooms/control/messages", r#"{"text": "hi"}"#).await;
+  assert_eq!(status, 400);
+  assert_eq!(body["error"], "InsufficientPeers");
+
+  let (status, body) = http(addr, "POST", "/rooms/other/messages", r#"{"text": "hi"}"#).await;
+  assert_eq!(
+    (status, body),
+    (
+      400,
+      json!({"id": null, "ok": false, "error": "Not in other."})
+    )
+  );
+
+  let (status, body) = http(addr, "POST", "/rooms/control/messages", "{}").await;
+  assert_eq!(status, 400);
+  assert!(body["error"].as_str().unwrap().starts_with("invalid body"));
+}
+
+#[tokio::test]
+async fn metrics_count_the_messages() {
+  let (addr, _) = spawn_api("control").await;
+  http(addr, "POST", "/rooms/control/messages", r#"{"text": "hi"}"#).await;
+  let (status, metrics) = http_text(addr, "GET", "/metrics", "").await;
+  assert_eq!(status, 200);
+  assert!(metrics.contains("chat_publish_failures_total 1\n"));
+  assert!(metrics.contains("chat_messages_sent_total 0\n"));
+  assert!(metrics.contains("libp2p_ping_rtt_seconds"));
+}
+
+#[tokio::test]
+async fn unknown_routes_are_rejected() {
+  let (addr, _) = spawn_api("control").await;
+  let (status, _) = http(addr, "GET", "/nowhere", "").await;
+  assert_eq!(status, 404);
+  let (status, _) = http(addr, "DELETE", "/rooms", "").await;
+  assert_eq!(status, 405);
+  let (status, _) = http(addr, "GET", "/rooms/control/messages?limit=many", "").await;
+  assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn requests_of_other_sites_are_refused() {
+  let (addr, _) = spawn_api("control").await;
+  let port = addr.port();
+  for host in [format!("localhost:{port}"), format!("127.0.0.1:{port}")] {
+    let (status, _) = raw_http(addr, "GET", "/rooms", &format!("Host: {host}"), "").await;
+    assert_eq!(status, 200, "{host}");
+  }
+  // As sent by a page of a domain rebound to 127.0.0.1
+  for headers in [
+    format!("Host: evil.example:{port}"),
+    "Host: localhost:1".to_string(),
+    "Accept: */*".to_string(),
+  ] {
+    let (status, _) = raw_http(addr, "GET", "/rooms", &headers, "").await;
+    assert_eq!(status, 403, "{headers}");
+  }
+  // As sent by a form
+  let (status, _) = raw_http(
+    addr,
+    "POST",
+    "/rooms/control/messages",
+    &format!("Host: {addr}\r\nContent-Type: text/plain"),
+    r#"{"text": "hi"}"#,
+  )
+  .await;
+  assert_eq!(status, 415);
+}
+
+/// Next event of the kind, skipping the others sent by the node.
+async fn next_event<S>(socket: &mut S, kind: &str) -> Value
+where
+  S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+{
+  while let Some(message) = socket.next().await {
+    let event: Value = serde_json::from_str(message.unwrap().to_text().unwrap()).unwrap();
+    if event["event"] == kind {
+      return event;
+    }
+  }
+  panic!("no {kind} event");
+}
+
+#[tokio::test]
+async fn websocket_streams_events_and_takes_commands() {
+  let (addr, events) = spawn_api("control").await;
+  let (mut socket, _) = connect_async(format!("ws://{addr}/events")).await.unwrap();
+
+  socket
+    .send(Message::text(r#"{"id": 7, "command": "rooms"}"#))
+    .await
+    .unwrap();
+  assert_eq!(
+    next_event(&mut socket, "result").await,
+    json!({"event": "result", "id": 7, "ok": true, "rooms": [{"name": "control", "private": false}]})
+  );
+
+  let event = ApiEvent::NicknameChanged {
+    peer_id: "12D3KooWFJX4F9yfcuYiERC44NrUcshwaQsZYXDk4ojF54Qzfswo".to_string(),
+    nickname: "bob".to_string(),
+  };
+  events.send(event.clone()).unwrap();
+  assert_eq!(
+    next_event(&mut socket, "nickname_changed").await,
+    serde_json::to_value(&event).unwrap()
+  );
+
+  // Plain requests are turned away
+  let (status, _) = http(addr, "GET", "/events", "").await;
+  assert_eq!(status, 426);
+}
+
+#[tokio::test]
+async fn websockets_of_other_sites_are_refused() {
+  let (addr, _) = spawn_api("control").await;
+  let with_origin = |origin: &str| {
+    let mut request = format!("ws://{addr}/events").into_client_request().unwrap();
+    request
+      .headers_mut()
+      .insert("Origin", origin.parse().unwrap());
+    request
+  };
+  assert!(connect_async(with_origin("http://localhost:3000"))
+    .await
+    .is_ok());
+  assert!(connect_async(with_origin("http://127.0.0.1")).await.is_ok());
+  assert!(connect_async(with_origin("https://evil.example"))
+    .await
+    .is_err());
+
+  // A key alone is not an upgrade
+  let headers = format!("Host: {addr}\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==");
+  let (status, _) = raw_http(addr, "GET", "/events", &headers, "").await;
+  assert_eq!(status, 426);
+}
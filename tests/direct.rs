@@ -0,0 +1,34 @@
+use libp2p::identity::Keypair;
+use rust_libp2p_chat::utils::{dm::DirectRequest, msg::Envelope};
+
+#[test]
+fn only_the_recipient_can_open_a_direct_message() {
+  let alice = Keypair::generate_ed25519();
+  let bob = Keypair::generate_ed25519();
+  let eve = Keypair::generate_ed25519();
+  let alice_id = alice.public().to_peer_id();
+  let bob_id = bob.public().to_peer_id();
+  let envelope = Envelope::text(Some("alice".to_string()), "secret");
+
+  let request = DirectRequest::seal(&alice, &bob_id, &envelope).unwrap();
+  assert_eq!(request.open(&bob, &alice_id).unwrap(), envelope);
+  assert!(request.open(&eve, &alice_id).is_err());
+}
+
+#[test]
+fn direct_message_is_bound_to_its_sender() {
+  let alice = Keypair::generate_ed25519();
+  let bob = Keypair::generate_ed25519();
+  let eve = Keypair::generate_ed25519();
+  let envelope = Envelope::text(None, "secret");
+
+  let request = DirectRequest::seal(&alice, &bob.public().to_peer_id(), &envelope).unwrap();
+  assert!(request.open(&bob, &eve.public().to_peer_id()).is_err());
+}
+
+#[test]
+fn direct_message_requires_an_ed25519_recipient() {
+  let alice = Keypair::generate_ed25519();
+  let envelope = Envelope::text(None, "secret");
+  assert!(DirectRequest::seal(&alice, &libp2p::PeerId::random(), &envelope).is_err());
+}
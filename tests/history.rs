@@ -0,0 +1,132 @@
+use rust_libp2p_chat::utils::{
+  history::{History, HistoryRecord, Retention},
+  msg::Envelope,
+};
+use std::{path::PathBuf, time::Duration};
+
+mod common;
+use common::temp_path;
+
+fn db_path(name: &str) -> PathBuf {
+  temp_path(name, "history")
+}
+
+fn record(room: &str, id: &str, body: &str) -> HistoryRecord {
+  HistoryRecord::new(room, id, "author", Envelope::text(None, body))
+}
+
+fn bodies(records: Vec<HistoryRecord>) -> Vec<String> {
+  records.into_iter().map(|r| r.envelope.body).collect()
+}
+
+#[test]
+fn last_messages_of_a_room_oldest_first() {
+  let path = db_path("last");
+  let history = History::open(&path, Retention::default()).unwrap();
+  for (i, room) in ["a", "b", "a", "a"].iter().enumerate() {
+    assert!(history
+      .insert(&record(room, &i.to_string(), &i.to_string()))
+      .unwrap());
+  }
+  assert_eq!(bodies(history.last("a", 2).unwrap()), ["2", "3"]);
+  assert_eq!(bodies(history.last("a", 10).unwrap()), ["0", "2", "3"]);
+  assert_eq!(bodies(history.last("b", 10).unwrap()), ["1"]);
+  assert!(history.last("c", 10).unwrap().is_empty());
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn messages_are_stored_once() {
+  let path = db_path("dedup");
+  let history = History::open(&path, Retention::default()).unwrap();
+  assert!(history.insert(&record("a", "id", "hi")).unwrap());
+  assert!(!history.insert(&record("a", "id", "hi")).unwrap());
+  assert!(history.contains("id").unwrap());
+  assert_eq!(history.len().unwrap(), 1);
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn history_survives_reopening() {
+  let path = db_path("reopen");
+  {
+    let history = History::open(&path, Retention::default()).unwrap();
+    history.insert(&record("a", "id", "hi")).unwrap();
+  }
+  let history = History::open(&path, Retention::default()).unwrap();
+  assert_eq!(bodies(history.last("a", 10).unwrap()), ["hi"]);
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn oldest_messages_beyond_the_count_are_dropped() {
+  let path = db_path("count");
+  let retention = Retention {
+    max_messages: Some(2),
+    max_age: None,
+  };
+  let history = History::open(&path, retention).unwrap();
+  for i in 0..4 {
+    history
+      .insert(&record("a", &i.to_string(), &i.to_string()))
+      .unwrap();
+  }
+  history.insert(&record("b", "b", "b")).unwrap();
+  assert_eq!(bodies(history.last("a", 10).unwrap()), ["2", "3"]);
+  assert_eq!(bodies(history.last("b", 10).unwrap()), ["b"]);
+  // Dropped ids can be stored again
+  assert!(!history.contains("0").unwrap());
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn messages_older_than_the_max_age_are_dropped() {
+  let path = db_path("age");
+  let retention = Retention {
+    max_messages: None,
+    max_age: Some(Duration::from_secs(3600)),
+  };
+  {
+    let history = History::open(&path, Retention::default()).unwrap();
+    let mut old = record("a", "old", "old");
+    old.received_at -= 2 * 3600 * 1000;
+    history.insert(&old).unwrap();
+    history.insert(&record("a", "new", "new")).unwrap();
+  }
+  let history = History::open(&path, retention).unwrap();
+  assert_eq!(bodies(history.last("a", 10).unwrap()), ["new"]);
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn messages_after_a_known_id_or_else_a_timestamp() {
+  let path = db_path("after");
+  let history = History::open(&path, Retention::default()).unwrap();
+  for i in 0..4u64 {
+    let mut record = record("a", &i.to_string(), &i.to_string());
+    record.received_at = 1_000 * (i + 1);
+    history.insert(&record).unwrap();
+  }
+  assert_eq!(
+    bodies(history.after("a", Some("1"), None, 10).unwrap()),
+    ["2", "3"]
+  );
+  assert_eq!(
+    bodies(history.after("a", Some("1"), None, 1).unwrap()),
+    ["3"]
+  );
+  assert_eq!(
+    bodies(
+      history
+        .after("a", Some("unknown"), Some(3_000), 10)
+        .unwrap()
+    ),
+    ["2", "3"]
+  );
+  // The id of another room is not a position in this one
+  assert_eq!(
+    bodies(history.after("b", Some("1"), None, 10).unwrap()),
+    Vec::<String>::new()
+  );
+  std::fs::remove_file(path).unwrap();
+}
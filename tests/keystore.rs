@@ -0,0 +1,49 @@
+use libp2p::identity::Keypair;
+use rust_libp2p_chat::utils::keystore;
+
+mod common;
+use common::temp_path;
+
+#[test]
+fn keystore_is_created_then_loaded() {
+  let path = temp_path("created", "key");
+  let (keypair, created) = keystore::load_or_create(&path, None).unwrap();
+  assert!(created);
+  let (loaded, created) = keystore::load_or_create(&path, None).unwrap();
+  assert!(!created);
+  assert_eq!(loaded.public(), keypair.public());
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn encrypted_keystore_requires_the_passphrase() {
+  let path = temp_path("encrypted", "key");
+  let keypair = Keypair::generate_ed25519();
+  keystore::save(&path, &keypair, Some("correct horse"), false).unwrap();
+
+  let loaded = keystore::load(&path, Some("correct horse")).unwrap();
+  assert_eq!(loaded.public(), keypair.public());
+  assert!(keystore::load(&path, Some("wrong")).is_err());
+  assert!(keystore::load(&path, None).is_err());
+  std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn keystore_is_not_overwritten_without_force() {
+  let path = temp_path("overwrite", "key");
+  keystore::save(&path, &Keypair::generate_ed25519(), None, false).unwrap();
+  assert!(keystore::save(&path, &Keypair::generate_ed25519(), None, false).is_err());
+  assert!(keystore::save(&path, &Keypair::generate_ed25519(), None, true).is_ok());
+  std::fs::remove_file(path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn keystore_readable_by_others_is_refused() {
+  use std::os::unix::fs::PermissionsExt;
+  let path = temp_path("public", "key");
+  keystore::save(&path, &Keypair::generate_ed25519(), None, false).unwrap();
+  std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+  assert!(keystore::load(&path, None).is_err());
+  std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,96 @@
+use libp2p::{gossipsub, identity::Keypair, swarm::SwarmEvent, Multiaddr, PeerId};
+use rust_libp2p_chat::{
+  node::MyBehaviourEvent,
+  utils::msg::{message_id, Envelope},
+  ChatEvent, ChatNode,
+};
+use std::time::Duration;
+use tokio::time::timeout;
+
+mod common;
+use common::listen_addr;
+
+const ROOM: &str = "test-room";
+
+fn message(source: PeerId, sequence_number: u64, data: &[u8]) -> gossipsub::Message {
+  gossipsub::Message {
+    source: Some(source),
+    data: data.to_vec(),
+    sequence_number: Some(sequence_number),
+    topic: gossipsub::IdentTopic::new(ROOM).hash(),
+  }
+}
+
+#[test]
+fn same_text_from_different_peers_has_different_ids() {
+  let alice = PeerId::random();
+  let bob = PeerId::random();
+  assert_ne!(
+    message_id(&message(alice, 1, b"hi")),
+    message_id(&message(bob, 1, b"hi"))
+  );
+}
+
+#[test]
+fn same_text_sent_twice_has_different_ids() {
+  let alice = PeerId::random();
+  assert_ne!(
+    message_id(&message(alice, 1, b"hi")),
+    message_id(&message(alice, 2, b"hi"))
+  );
+}
+
+#[test]
+fn message_id_is_stable() {
+  let alice = PeerId::random();
+  assert_eq!(
+    message_id(&message(alice, 1, b"hi")),
+    message_id(&message(alice, 1, b"hi"))
+  );
+}
+
+/// Dial `addr`, publish `envelope` once the remote joined the room, then keep the node running.
+fn spawn_sender(addr: Multiaddr, envelope: Envelope) {
+  let mut node = ChatNode::builder()
+    .keypair(Keypair::generate_ed25519())
+    .topic(ROOM)
+    .build()
+    .unwrap();
+  node.swarm_mut().dial(addr).unwrap();
+  tokio::spawn(async move {
+    loop {
+      if let ChatEvent::Other(SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(
+        gossipsub::Event::Subscribed { topic, .. },
+      ))) = node.next_event().await
+      {
+        if topic != gossipsub::IdentTopic::new(ROOM).hash() {
+          continue;
+        }
+        node.publish(ROOM, &envelope).unwrap();
+      }
+    }
+  });
+}
+
+#[tokio::test]
+async fn identical_texts_from_different_peers_are_delivered() {
+  let mut receiver = ChatNode::builder().topic(ROOM).build().unwrap();
+  let addr = listen_addr(&mut receiver).await;
+  // The same envelope, timestamp included, so that only the source and seqno tell them apart
+  let envelope = Envelope::text(None, "hi");
+  spawn_sender(addr.clone(), envelope.clone());
+  spawn_sender(addr, envelope.clone());
+
+  let mut sources = Vec::new();
+  timeout(Duration::from_secs(30), async {
+    while sources.len() < 2 {
+      if let ChatEvent::Message { message, .. } = receiver.next_event().await {
+        assert_eq!(message.data, envelope.encode());
+        sources.push(message.source.unwrap());
+      }
+    }
+  })
+  .await
+  .expect("both messages should be delivered");
+  assert_ne!(sources[0], sources[1]);
+}
@@ -0,0 +1,27 @@
+use libp2p::Multiaddr;
+use rust_libp2p_chat::utils::peer::quic_first;
+
+#[test]
+fn quic_addresses_are_sorted_first() {
+  let mut addrs: Vec<Multiaddr> = [
+    "/ip4/10.0.0.1/tcp/8080",
+    "/ip4/10.0.0.1/udp/8080/quic-v1",
+    "/dns4/example.com/tcp/443/wss",
+    "/ip6/::1/udp/8080/quic-v1",
+  ]
+  .iter()
+  .map(|addr| addr.parse().unwrap())
+  .collect();
+  quic_first(&mut addrs);
+  let addrs: Vec<String> = addrs.iter().map(ToString::to_string).collect();
+  // The order of each transport is kept
+  assert_eq!(
+    addrs,
+    [
+      "/ip4/10.0.0.1/udp/8080/quic-v1",
+      "/ip6/::1/udp/8080/quic-v1",
+      "/ip4/10.0.0.1/tcp/8080",
+      "/dns4/example.com/tcp/443/wss",
+    ]
+  );
+}
@@ -0,0 +1,185 @@
+use rust_libp2p_chat::{
+  node::Rejection,
+  utils::{
+    msg::{Envelope, Kind},
+    room::RoomKey,
+  },
+  ChatEvent, ChatNode,
+};
+use std::time::Duration;
+use tokio::time::{interval, timeout};
+
+mod common;
+use common::listen_addr;
+
+#[test]
+fn room_members_open_room_messages() {
+  let key = RoomKey::generate("secret", 0);
+  let envelope = Envelope::text(Some("alice".to_string()), "hello members");
+
+  let sealed = key.seal(&envelope).unwrap();
+  assert_eq!(sealed.kind, Kind::Encrypted);
+  assert_eq!(sealed.sender, None);
+  assert_eq!(key.open(&sealed).unwrap(), envelope);
+}
+
+#[test]
+fn rotated_room_key_does_not_open_new_messages() {
+  let old = RoomKey::generate("secret", 0);
+  let new = RoomKey::generate("secret", 1);
+  let sealed = new.seal(&Envelope::text(None, "after kick")).unwrap();
+  assert!(old.open(&sealed).is_err());
+}
+
+#[test]
+fn room_key_is_bound_to_its_room() {
+  let mut key = RoomKey::generate("secret", 0);
+  let sealed = key.seal(&Envelope::text(None, "hello")).unwrap();
+  key.room = "other".to_string();
+  assert!(key.open(&sealed).is_err());
+}
+
+#[tokio::test]
+async fn unsealed_messages_of_private_rooms_are_rejected() {
+  let mut owner = ChatNode::builder().quic(false).build().unwrap();
+  owner.create_private_room("secret").unwrap();
+  let addr = listen_addr(&mut owner).await;
+  // Knows the name of the room, not its key
+  let mut eve = ChatNode::builder()
+    .quic(false)
+    .topic("secret")
+    .build()
+    .unwrap();
+  eve.swarm_mut().dial(addr).unwrap();
+
+  let mut tick = interval(Duration::from_millis(200));
+  let reason = timeout(Duration::from_secs(30), async {
+    loop {
+      tokio::select! {
+        _ = tick.tick() => {
+          let _ = eve.send_text("secret", "in the clear");
+        }
+        event = owner.next_event() => match event {
+          ChatEvent::Message { envelope, .. } => panic!("shown: {envelope:?}"),
+          ChatEvent::MessageRejected { reason, .. } => return reason,
+          _ => {}
+        },
+        _ = eve.next_event() => {}
+      }
+    }
+  })
+  .await
+  .expect("the message should reach the owner");
+  assert!(matches!(reason, Rejection::Malformed(_)), "{reason:?}");
+}
+
+#[tokio::test]
+async fn invitations_are_kept_until_joined_and_forgotten_on_leave() {
+  let mut owner = ChatNode::builder().quic(false).build().unwrap();
+  owner.create_private_room("secret").unwrap();
+  let addr = listen_addr(&mut owner).await;
+  let mut guest = ChatNode::builder().quic(false).build().unwrap();
+  guest.swarm_mut().dial(addr).unwrap();
+  let (owner_id, guest_id) = (owner.local_peer_id(), guest.local_peer_id());
+
+  timeout(Duration::from_secs(30), async {
+    loop {
+      tokio::select! {
+        event = owner.next_event() => {
+          if let ChatEvent::PeerConnected { peer_id, .. } = event {
+            assert_eq!(peer_id, guest_id);
+            owner.invite("secret", guest_id).unwrap();
+          }
+        }
+        event = guest.next_event() => {
+          if let ChatEvent::RoomKeyReceived { room, peer_id, .. } = event {
+            assert_eq!((room.as_str(), peer_id), ("secret", owner_id));
+            break;
+          }
+        }
+      }
+    }
+  })
+  .await
+  .expect("the invitation should be delivered");
+
+  // The key is not used before the room is joined
+  assert!(!guest.is_private("secret"));
+  assert_eq!(guest.invitations("secret"), [owner_id]);
+  guest.subscribe("secret").unwrap();
+  assert!(guest.is_private("secret"));
+  assert!(guest.invitations("secret").is_empty());
+
+  // Joining again after leaving does not reuse the old key
+  guest.unsubscribe("secret").unwrap();
+  assert!(!guest.is_private("secret"));
+  guest.subscribe("secret").unwrap();
+  assert!(!guest.is_private("secret"));
+}
+
+#[tokio::test]
+async fn invitations_are_kept_per_owner_and_limited_per_peer() {
+  let mut guest = ChatNode::builder().quic(false).build().unwrap();
+  let addr = listen_addr(&mut guest).await;
+  let mut owner = ChatNode::builder().quic(false).build().unwrap();
+  owner.create_private_room("secret").unwrap();
+  owner.swarm_mut().dial(addr.clone()).unwrap();
+  // Squats the room name and tries to fill the invitations
+  let rooms = ["secret", "squat-1", "squat-2", "squat-3", "squat-4"];
+  let mut squatter = ChatNode::builder().quic(false).build().unwrap();
+  for room in rooms {
+    squatter.create_private_room(room).unwrap();
+  }
+  squatter.swarm_mut().dial(addr).unwrap();
+  let (owner_id, squatter_id, guest_id) = (
+    owner.local_peer_id(),
+    squatter.local_peer_id(),
+    guest.local_peer_id(),
+  );
+
+  let (mut received, mut refused) = (0, 0);
+  timeout(Duration::from_secs(30), async {
+    while received < 5 || refused < 1 {
+      tokio::select! {
+        event = guest.next_event() => {
+          if let ChatEvent::RoomKeyReceived { .. } = event {
+            received += 1;
+          }
+        }
+        event = owner.next_event() => {
+          if let ChatEvent::PeerConnected { peer_id, .. } = event {
+            assert_eq!(peer_id, guest_id);
+            owner.invite("secret", guest_id).unwrap();
+          }
+        }
+        event = squatter.next_event() => match event {
+          ChatEvent::PeerConnected { peer_id, .. } => {
+            assert_eq!(peer_id, guest_id);
+            for room in rooms {
+              squatter.invite(room, guest_id).unwrap();
+            }
+          }
+          ChatEvent::DirectFailed { .. } => refused += 1,
+          _ => {}
+        },
+      }
+    }
+  })
+  .await
+  .expect("the invitations should be delivered or refused");
+
+  let squatted: usize = rooms.iter().map(|room| guest.invitations(room).len()).sum();
+  assert_eq!(squatted, 5, "1 of the owner and 4 of the squatter");
+  // Unless the invitation of the squatter to this room was the refused one
+  let invited = guest.invitations("secret");
+  assert!(invited.contains(&owner_id));
+
+  let declined = guest.decline_invitation("secret", Some(squatter_id));
+  assert_eq!(declined, invited.len() - 1);
+  assert_eq!(guest.invitations("secret"), [owner_id]);
+  guest.subscribe("secret").unwrap();
+  assert!(guest.is_private("secret"));
+  let pending = guest.invitations("squat-1").len();
+  assert_eq!(guest.decline_invitation("squat-1", None), pending);
+  assert!(guest.invitations("squat-1").is_empty());
+}
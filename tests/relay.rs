@@ -0,0 +1,109 @@
+use libp2p::{
+  core::{ConnectedPoint, Endpoint},
+  identity::Keypair,
+  multiaddr::Protocol,
+  swarm::SwarmEvent,
+  Multiaddr, PeerId,
+};
+use rust_libp2p_chat::{
+  config::Config,
+  node::ConnectionPath,
+  server::{ServerEvent, ServerNode},
+  ChatEvent, ChatNode,
+};
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// Start a relay on loopback and keep it running. Returns its address.
+async fn spawn_relay() -> Multiaddr {
+  let mut config = Config::default();
+  config.network.quic = false;
+  config.server.relay = true;
+  let mut relay = ServerNode::new(Keypair::generate_ed25519(), &config).unwrap();
+  let addr = loop {
+    if let ServerEvent::ListenAddr(addr) = relay.next_event().await {
+      if addr.to_string().starts_with("/ip4/127.0.0.1/") {
+        break addr;
+      }
+    }
+  };
+  // Relays only accept reservations once they know their public address
+  relay.swarm_mut().add_external_address(addr.clone());
+  tokio::spawn(async move {
+    loop {
+      relay.next_event().await;
+    }
+  });
+  addr
+}
+
+#[test]
+fn connection_path_tells_relayed_connections_apart() {
+  let relay = PeerId::random();
+  let direct: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+  let relayed = direct
+    .clone()
+    .with(Protocol::P2p(relay))
+    .with(Protocol::P2pCircuit);
+  let dialer = |address: Multiaddr| ConnectedPoint::Dialer {
+    address,
+    role_override: Endpoint::Dialer,
+  };
+  assert_eq!(
+    ConnectionPath::of(&dialer(direct.clone())),
+    ConnectionPath::Direct(direct)
+  );
+  assert_eq!(
+    ConnectionPath::of(&dialer(relayed)),
+    ConnectionPath::Relayed { relay }
+  );
+}
+
+#[tokio::test]
+async fn peers_connect_through_a_relay() {
+  let relay_addr = spawn_relay().await;
+  let relay_id = match relay_addr.iter().last() {
+    Some(Protocol::P2p(peer_id)) => peer_id,
+    _ => unreachable!("listen addresses end with the peer id"),
+  };
+
+  let mut listener = ChatNode::builder().quic(false).build().unwrap();
+  let circuit = relay_addr.clone().with(Protocol::P2pCircuit);
+  listener.swarm_mut().listen_on(circuit.clone()).unwrap();
+  timeout(Duration::from_secs(30), async {
+    loop {
+      if let ChatEvent::RelayReserved { relay } = listener.next_event().await {
+        assert_eq!(relay, relay_id);
+        break;
+      }
+    }
+  })
+  .await
+  .expect("the relay should accept the reservation");
+
+  let listener_id = listener.local_peer_id();
+  let mut dialer = ChatNode::builder().quic(false).build().unwrap();
+  dialer
+    .swarm_mut()
+    .dial(circuit.with(Protocol::P2p(listener_id)))
+    .unwrap();
+  timeout(Duration::from_secs(30), async {
+    loop {
+      tokio::select! {
+        event = dialer.next_event() => match event {
+          ChatEvent::PeerConnected { peer_id, path } if peer_id == listener_id => {
+            assert_eq!(path, ConnectionPath::Relayed { relay: relay_id });
+            break;
+          }
+          ChatEvent::Other(SwarmEvent::OutgoingConnectionError { error, .. }) => {
+            panic!("failed to dial through the relay: {error}")
+          }
+          _ => {}
+        },
+        _ = listener.next_event() => {}
+      }
+    }
+  })
+  .await
+  .expect("the dialer should connect through the relay");
+}
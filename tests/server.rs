@@ -0,0 +1,50 @@
+use libp2p::identity::Keypair;
+use rust_libp2p_chat::{
+  config::Config,
+  server::{health::Status, ServerEvent, ServerNode},
+  ChatEvent, ChatNode,
+};
+use std::time::Duration;
+use tokio::{sync::oneshot, time::timeout};
+
+#[tokio::test]
+async fn shutdown_closes_the_connections() {
+  let mut config = Config::default();
+  config.network.quic = false;
+  let mut server = ServerNode::new(Keypair::generate_ed25519(), &config).unwrap();
+  let server_id = server.local_peer_id();
+  let addr = loop {
+    if let ServerEvent::ListenAddr(addr) = server.next_event().await {
+      if addr.to_string().starts_with("/ip4/127.0.0.1/") {
+        break addr;
+      }
+    }
+  };
+  let mut peer = ChatNode::builder().quic(false).build().unwrap();
+  peer.swarm_mut().dial(addr).unwrap();
+  let (disconnected, closed) = oneshot::channel();
+  tokio::spawn(async move {
+    loop {
+      if let ChatEvent::PeerDisconnected(peer_id) = peer.next_event().await {
+        if peer_id == server_id {
+          let _ = disconnected.send(());
+          return;
+        }
+      }
+    }
+  });
+  timeout(Duration::from_secs(30), async {
+    while !matches!(server.next_event().await, ServerEvent::PeerConnected(_)) {}
+  })
+  .await
+  .expect("the peer should connect");
+
+  let health = server.health();
+  server.shutdown().await;
+  assert_eq!(health.borrow().status, Status::Stopping);
+  assert_eq!(server.swarm().connected_peers().count(), 0);
+  timeout(Duration::from_secs(5), closed)
+    .await
+    .expect("the peer should be told")
+    .unwrap();
+}
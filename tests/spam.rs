@@ -0,0 +1,139 @@
+use libp2p::{gossipsub, swarm::SwarmEvent, PeerId};
+use rust_libp2p_chat::{
+  node::{MyBehaviourEvent, Rejection},
+  utils::nick::PRESENCE_TOPIC,
+  ChatEvent, ChatNode,
+};
+use std::time::Duration;
+use tokio::time::{interval, sleep, timeout};
+
+mod common;
+use common::listen_addr;
+
+const ROOM: &str = "spam-test-room";
+
+/// What the receiver got from the room.
+#[derive(Default)]
+struct Outcome {
+  received: Vec<String>,
+  rejected: Vec<(PeerId, Rejection)>,
+}
+
+impl Outcome {
+  fn record(&mut self, event: ChatEvent) {
+    match event {
+      ChatEvent::Message { envelope, .. } => self.received.push(envelope.body),
+      ChatEvent::NicknameChanged { nickname, .. } => self.received.push(nickname),
+      ChatEvent::MessageRejected { author, reason, .. } => self.rejected.push((author, reason)),
+      _ => {}
+    }
+  }
+}
+
+#[tokio::test]
+async fn messages_over_the_rate_limit_are_rejected_once_reported() {
+  let mut receiver = ChatNode::builder()
+    .quic(false)
+    .rate_limit(2, Duration::from_secs(60))
+    .topic(ROOM)
+    .build()
+    .unwrap();
+  let addr = listen_addr(&mut receiver).await;
+  let mut flooder = ChatNode::builder().quic(false).topic(ROOM).build().unwrap();
+  flooder.swarm_mut().dial(addr).unwrap();
+  let flooder_id = flooder.local_peer_id();
+
+  let mut outcome = Outcome::default();
+  let mut sent = 0;
+  let mut tick = interval(Duration::from_millis(200));
+  let result = timeout(Duration::from_secs(30), async {
+    // Publishing fails until the flooder knows the receiver joined the room
+    while sent < 5 {
+      tokio::select! {
+        _ = tick.tick() => {
+          if flooder.send_text(ROOM, format!("spam {sent}")).is_ok() {
+            sent += 1;
+          }
+        }
+        event = receiver.next_event() => outcome.record(event),
+        _ = flooder.next_event() => {}
+      }
+    }
+    // Let the last ones arrive
+    let settle = sleep(Duration::from_secs(2));
+    tokio::pin!(settle);
+    loop {
+      tokio::select! {
+        _ = &mut settle => break,
+        event = receiver.next_event() => outcome.record(event),
+        _ = flooder.next_event() => {}
+      }
+    }
+  })
+  .await;
+  assert!(result.is_ok(), "the flooder should publish its messages");
+  assert_eq!(outcome.received, ["spam 0", "spam 1"]);
+  assert_eq!(outcome.rejected, [(flooder_id, Rejection::RateLimited)]);
+}
+
+#[tokio::test]
+async fn nickname_floods_are_rate_limited() {
+  let mut receiver = ChatNode::builder().quic(false).build().unwrap();
+  let addr = listen_addr(&mut receiver).await;
+  let mut flooder = ChatNode::builder().quic(false).build().unwrap();
+  flooder.swarm_mut().dial(addr).unwrap();
+  let flooder_id = flooder.local_peer_id();
+  let presence = gossipsub::IdentTopic::new(PRESENCE_TOPIC).hash();
+
+  let mut outcome = Outcome::default();
+  let result = timeout(Duration::from_secs(30), async {
+    // Records are only published once the receiver joined the presence topic
+    loop {
+      tokio::select! {
+        event = flooder.next_event() => {
+          if let ChatEvent::Other(SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(
+            gossipsub::Event::Subscribed { topic, .. },
+          ))) = event
+          {
+            if topic == presence {
+              break;
+            }
+          }
+        }
+        event = receiver.next_event() => outcome.record(event),
+      }
+    }
+    for i in 0..100 {
+      flooder.set_nickname(&format!("nick{i}")).unwrap();
+    }
+    let settle = sleep(Duration::from_secs(3));
+    tokio::pin!(settle);
+    loop {
+      tokio::select! {
+        _ = &mut settle => break,
+        event = receiver.next_event() => outcome.record(event),
+        _ = flooder.next_event() => {}
+      }
+    }
+  })
+  .await;
+  assert!(result.is_ok(), "the flooder should reach the receiver");
+  assert!(outcome.received.len() <= 31, "got {:?}", outcome.received);
+  assert_eq!(outcome.rejected, [(flooder_id, Rejection::RateLimited)]);
+}
+
+#[tokio::test]
+async fn rooms_stop_counting_in_the_score_once_left() {
+  let mut node = ChatNode::builder().quic(false).build().unwrap();
+  let topic = gossipsub::IdentTopic::new(ROOM);
+  let weight = |node: &ChatNode| {
+    let gossipsub = &node.swarm().behaviour().gossipsub;
+    gossipsub
+      .get_topic_params(&topic)
+      .map(|params| params.topic_weight)
+  };
+  node.subscribe(ROOM).unwrap();
+  assert_eq!(weight(&node), Some(1.0));
+  node.unsubscribe(ROOM).unwrap();
+  assert_eq!(weight(&node), Some(0.0));
+}
@@ -0,0 +1,226 @@
+use libp2p::{identity::Keypair, Multiaddr, PeerId};
+use rust_libp2p_chat::{
+  utils::{
+    history::{History, HistoryRecord, Retention},
+    msg::{message_id, Envelope, SignedMessage},
+  },
+  ChatEvent, ChatNode,
+};
+use std::{path::PathBuf, time::Duration};
+use tokio::time::{interval, timeout};
+
+mod common;
+use common::{listen_addr, temp_path};
+
+const ROOM: &str = "sync-test-room";
+
+/// A message of the room, as published and signed by its author.
+fn signed_record(author: &Keypair, seqno: u64, body: &str) -> HistoryRecord {
+  let envelope = Envelope::text(None, body);
+  let signed = SignedMessage::sign(author, ROOM, seqno, envelope.encode()).unwrap();
+  let peer_id = author.public().to_peer_id();
+  let id = message_id(&signed.verify(&peer_id, ROOM).unwrap());
+  HistoryRecord {
+    received_at: 1_000 * seqno,
+    signed: Some(signed),
+    ..HistoryRecord::new(ROOM, &id.to_string(), &peer_id.to_string(), envelope)
+  }
+}
+
+fn history_with(name: &str, records: &[HistoryRecord]) -> (PathBuf, History) {
+  let path = temp_path(name, "history");
+  let history = History::open(&path, Retention::default()).unwrap();
+  for record in records {
+    history.insert(record).unwrap();
+  }
+  (path, history)
+}
+
+fn bodies(records: Vec<HistoryRecord>) -> Vec<String> {
+  records
+    .into_iter()
+    .map(|record| record.envelope.body)
+    .collect()
+}
+
+/// Connect a peer joined late to the online one, and return the messages it synced.
+async fn sync_from(
+  online: &mut ChatNode,
+  addr: Multiaddr,
+  late: &mut ChatNode,
+) -> Vec<HistoryRecord> {
+  late.swarm_mut().dial(addr).unwrap();
+  timeout(Duration::from_secs(30), async {
+    loop {
+      tokio::select! {
+        event = late.next_event() => {
+          if let ChatEvent::HistorySynced { room, records, .. } = event {
+            assert_eq!(room, ROOM);
+            break records;
+          }
+        }
+        _ = online.next_event() => {}
+      }
+    }
+  })
+  .await
+  .expect("the late peer should sync the room")
+}
+
+#[test]
+fn signed_messages_are_bound_to_their_author_and_room() {
+  let author = Keypair::generate_ed25519();
+  let peer_id = author.public().to_peer_id();
+  let signed = SignedMessage::sign(&author, ROOM, 1, b"hi".to_vec()).unwrap();
+  let message = signed.verify(&peer_id, ROOM).unwrap();
+  assert_eq!(message.data, b"hi");
+
+  assert!(signed.verify(&PeerId::random(), ROOM).is_err());
+  assert!(signed.verify(&peer_id, "other").is_err());
+  let mut tampered = signed.clone();
+  tampered.data = b"bye".to_vec();
+  assert!(tampered.verify(&peer_id, ROOM).is_err());
+  let mut replayed = signed;
+  replayed.seqno = 2;
+  assert!(replayed.verify(&peer_id, ROOM).is_err());
+}
+
+#[tokio::test]
+async fn late_peer_gets_the_messages_it_missed() {
+  let author = Keypair::generate_ed25519();
+  let messages = ["first", "second", "third"].map(|body| body.to_string());
+  let records = (1..)
+    .zip(&messages)
+    .map(|(seqno, body)| signed_record(&author, seqno, body));
+  let records = records.collect::<Vec<_>>();
+  let (online_path, online_history) = history_with("online", &records);
+  let mut online = ChatNode::builder()
+    .quic(false)
+    .history(online_history)
+    .topic(ROOM)
+    .build()
+    .unwrap();
+  let addr = listen_addr(&mut online).await;
+
+  // It left after the first message
+  let (late_path, late_history) = history_with("late", &records[..1]);
+  let mut late = ChatNode::builder()
+    .quic(false)
+    .history(late_history)
+    .topic(ROOM)
+    .build()
+    .unwrap();
+
+  let records = sync_from(&mut online, addr, &mut late).await;
+  assert_eq!(bodies(records), ["second", "third"]);
+  assert_eq!(bodies(late.history(ROOM, 10).unwrap()), messages);
+
+  drop((online, late));
+  std::fs::remove_file(online_path).unwrap();
+  std::fs::remove_file(late_path).unwrap();
+}
+
+#[tokio::test]
+async fn forged_messages_are_not_synced() {
+  let author = Keypair::generate_ed25519();
+  let mut tampered = signed_record(&author, 2, "tampered");
+  tampered.signed.as_mut().unwrap().data = Envelope::text(None, "forged").encode();
+  let mut impersonated = signed_record(&author, 3, "impersonated");
+  impersonated.author = PeerId::random().to_string();
+  let mut renamed = signed_record(&author, 4, "renamed");
+  renamed.message_id = "renamed".to_string();
+  let mut unsigned = signed_record(&author, 5, "unsigned");
+  unsigned.signed = None;
+  // Not served, without failing the whole sync
+  let mut undecodable = signed_record(&author, 6, "undecodable");
+  undecodable.signed.as_mut().unwrap().data = b"not an envelope".to_vec();
+  let records = [
+    signed_record(&author, 1, "genuine"),
+    tampered,
+    impersonated,
+    renamed,
+    unsigned,
+    undecodable,
+  ];
+  let (online_path, online_history) = history_with("forger", &records);
+  let mut online = ChatNode::builder()
+    .quic(false)
+    .history(online_history)
+    .topic(ROOM)
+    .build()
+    .unwrap();
+  let addr = listen_addr(&mut online).await;
+  let (late_path, late_history) = history_with("forged", &[]);
+  let mut late = ChatNode::builder()
+    .quic(false)
+    .history(late_history)
+    .topic(ROOM)
+    .build()
+    .unwrap();
+
+  let records = sync_from(&mut online, addr, &mut late).await;
+  assert_eq!(bodies(records), ["genuine"]);
+  assert_eq!(bodies(late.history(ROOM, 10).unwrap()), ["genuine"]);
+
+  drop((online, late));
+  std::fs::remove_file(online_path).unwrap();
+  std::fs::remove_file(late_path).unwrap();
+}
+
+#[tokio::test]
+async fn relayed_messages_are_synced_with_the_signature_of_their_author() {
+  let (bob_path, bob_history) = history_with("relay", &[]);
+  let mut bob = ChatNode::builder()
+    .quic(false)
+    .history(bob_history)
+    .topic(ROOM)
+    .build()
+    .unwrap();
+  let addr = listen_addr(&mut bob).await;
+  let mut alice = ChatNode::builder().quic(false).topic(ROOM).build().unwrap();
+  alice.swarm_mut().dial(addr.clone()).unwrap();
+
+  let mut sent = false;
+  let mut tick = interval(Duration::from_millis(200));
+  timeout(Duration::from_secs(30), async {
+    loop {
+      tokio::select! {
+        _ = tick.tick(), if !sent => {
+          // Publishing fails until Bob knows Alice joined the room
+          sent = alice.send_text(ROOM, "from alice").is_ok();
+        }
+        event = bob.next_event() => {
+          if let ChatEvent::Message { .. } = event {
+            break;
+          }
+        }
+        _ = alice.next_event() => {}
+      }
+    }
+  })
+  .await
+  .expect("Bob should receive the message of Alice");
+  bob.send_text(ROOM, "from bob").unwrap();
+
+  let (carol_path, carol_history) = history_with("carol", &[]);
+  let mut carol = ChatNode::builder()
+    .quic(false)
+    .history(carol_history)
+    .topic(ROOM)
+    .build()
+    .unwrap();
+  let records = sync_from(&mut bob, addr, &mut carol).await;
+  let authors = records
+    .iter()
+    .map(|record| record.author.clone())
+    .collect::<Vec<_>>();
+  assert_eq!(bodies(records), ["from alice", "from bob"]);
+  assert_eq!(
+    authors,
+    [alice.local_peer_id(), bob.local_peer_id()].map(|peer_id| peer_id.to_string())
+  );
+
+  drop((alice, bob, carol));
+  std::fs::remove_file(bob_path).unwrap();
+  std::fs::remove_file(carol_path).unwrap();
+}
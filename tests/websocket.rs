@@ -0,0 +1,45 @@
+use libp2p::{multiaddr::Protocol, Multiaddr};
+use rust_libp2p_chat::{node::ConnectionPath, utils::ws::WsListener, ChatEvent, ChatNode};
+use std::time::Duration;
+use tokio::time::timeout;
+
+#[tokio::test]
+async fn peers_connect_over_websocket() {
+  let mut server = ChatNode::builder()
+    .quic(false)
+    .websocket(WsListener { port: 0, tls: None })
+    .build()
+    .unwrap();
+  let addr: Multiaddr = loop {
+    if let ChatEvent::ListenAddr(addr) = server.next_event().await {
+      if addr.iter().any(|p| matches!(p, Protocol::Ws(_)))
+        && addr.to_string().starts_with("/ip4/127.0.0.1/")
+      {
+        break addr;
+      }
+    }
+  };
+
+  let server_id = server.local_peer_id();
+  let mut client = ChatNode::builder().quic(false).build().unwrap();
+  client.swarm_mut().dial(addr).unwrap();
+  timeout(Duration::from_secs(30), async {
+    loop {
+      tokio::select! {
+        event = client.next_event() => {
+          if let ChatEvent::PeerConnected { peer_id, path } = event {
+            assert_eq!(peer_id, server_id);
+            let ConnectionPath::Direct(addr) = path else {
+              panic!("expected a direct connection");
+            };
+            assert!(addr.iter().any(|p| matches!(p, Protocol::Ws(_))));
+            break;
+          }
+        }
+        _ = server.next_event() => {}
+      }
+    }
+  })
+  .await
+  .expect("the client should connect over WebSocket");
+}